@@ -0,0 +1,268 @@
+use std::future::Future;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use tokio::fs;
+
+use crate::gitignore::Gitignore;
+use crate::tree::{
+    crosses_filesystem, file_limit_note, finish_directory, includes_subtree, passes_filters,
+    passes_metadata_filters, with_treeignore, LargestFiles, TreeConfig, TreeEntry, TreeStats,
+    TREEIGNORE_FILE,
+};
+
+/// State handed down from ancestor directories during a walk
+#[derive(Clone)]
+struct Ancestry<'a> {
+    /// `.gitignore` rules in effect, innermost last
+    ignores: Vec<Gitignore>,
+    /// Canonical paths of ancestor directories, tracked when following symlinks
+    visited: Vec<PathBuf>,
+    /// Device of the root directory when staying on one filesystem
+    root_dev: Option<u64>,
+    /// Whether this is the path the walk started from
+    top_level: bool,
+    /// The path the walk started from, for root-relative pattern matching
+    root: &'a Path,
+    /// Inside a directory matched under `match_dirs`
+    include_all: bool,
+}
+
+/// Walk a directory like [`walk_directory`](crate::tree::walk_directory)
+/// without blocking the runtime, using `tokio::fs` for every lookup
+///
+/// Produces the same tree and stats for the same `config`. Directories are
+/// read one at a time, so `threads` is ignored.
+pub async fn async_walk_directory(
+    path: &Path,
+    config: &TreeConfig,
+    stats: &mut TreeStats,
+    current_depth: usize,
+) -> TreeEntry {
+    let treeignore = if config.respect_treeignore {
+        fs::read_to_string(path.join(TREEIGNORE_FILE)).await.ok()
+    } else {
+        None
+    };
+    let (config, treeignore_error) = with_treeignore(config, treeignore.as_deref());
+    let config = &*config;
+    stats.depth_limit = config.max_depth;
+    stats.largest_files = LargestFiles::new(config.top_files);
+
+    let root_dev = if config.stay_on_filesystem {
+        fs::metadata(path).await.ok().map(|m| m.dev())
+    } else {
+        None
+    };
+    let ancestry = Ancestry {
+        ignores: Vec::new(),
+        visited: Vec::new(),
+        root_dev,
+        top_level: true,
+        root: path,
+        include_all: false,
+    };
+    let mut tree = walk(path.to_path_buf(), config, stats, current_depth, ancestry).await;
+    if let Some(error) = treeignore_error {
+        tree.error.get_or_insert(error);
+        stats.errors += 1;
+    }
+    tree
+}
+
+/// Look up an entry the way [`TreeEntry::new`] does
+async fn read_entry(path: PathBuf) -> TreeEntry {
+    let name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string());
+
+    let is_symlink = fs::symlink_metadata(&path)
+        .await
+        .is_ok_and(|m| m.is_symlink());
+    let metadata = fs::metadata(&path).await.ok();
+    let symlink_target = if is_symlink {
+        fs::read_link(&path).await.ok()
+    } else {
+        None
+    };
+
+    TreeEntry::from_parts(path, name, is_symlink, symlink_target, metadata)
+}
+
+// Recursion in an async fn needs the future boxed
+fn walk<'a>(
+    path: PathBuf,
+    config: &'a TreeConfig,
+    stats: &'a mut TreeStats,
+    current_depth: usize,
+    mut ancestry: Ancestry<'a>,
+) -> Pin<Box<dyn Future<Output = TreeEntry> + Send + 'a>> {
+    Box::pin(async move {
+        let mut entry = read_entry(path).await;
+        entry.depth = current_depth;
+
+        if !entry.is_dir {
+            return entry;
+        }
+
+        // Check depth limit; entries at `max_depth` are listed but not descended
+        if config.max_depth.is_some_and(|max| current_depth >= max) {
+            entry.unread = true;
+            return entry;
+        }
+
+        let follow = config.follow_symlinks || (config.dereference_args && ancestry.top_level);
+        if entry.is_symlink && !follow {
+            entry.unread = true;
+            return entry;
+        }
+
+        // Mount points are listed but not descended with -x
+        let dev = entry.metadata.as_ref().map(|m| m.dev());
+        if crosses_filesystem(ancestry.root_dev, dev) {
+            entry.unread = true;
+            return entry;
+        }
+
+        ancestry.top_level = false;
+        if config.follow_symlinks {
+            let canonical = fs::canonicalize(&entry.path)
+                .await
+                .unwrap_or_else(|_| entry.path.clone());
+            if ancestry.visited.contains(&canonical) {
+                entry.note = Some("recursive, not followed".to_string());
+                return entry;
+            }
+            ancestry.visited.push(canonical);
+        }
+
+        let mut read_dir = match fs::read_dir(&entry.path).await {
+            Ok(rd) => rd,
+            Err(e) => {
+                entry.error = Some(format!("error opening dir: {}", e));
+                stats.errors += 1;
+                return entry;
+            }
+        };
+
+        // Rules from this directory's .gitignore apply to its whole subtree
+        if config.respect_gitignore {
+            if let Ok(content) = fs::read_to_string(entry.path.join(".gitignore")).await {
+                ancestry
+                    .ignores
+                    .push(Gitignore::parse(&entry.path, &content));
+            }
+        }
+
+        let mut child_paths = Vec::new();
+        while let Some(dir_entry) = read_dir.next_entry().await.ok().flatten() {
+            let child_path = dir_entry.path();
+            let child_name = dir_entry.file_name().to_string_lossy().to_string();
+
+            // Hidden names are skipped before paying for a stat
+            if !config.show_hidden && child_name.starts_with('.') {
+                continue;
+            }
+
+            let child_meta = fs::metadata(&child_path).await.ok();
+            let child_is_dir = child_meta.as_ref().is_some_and(|m| m.is_dir());
+            if !passes_filters(
+                config,
+                ancestry.root,
+                &ancestry.ignores,
+                ancestry.include_all,
+                &child_path,
+                &child_name,
+                child_is_dir,
+            ) {
+                continue;
+            }
+
+            if !passes_metadata_filters(config, child_meta.as_ref()) {
+                continue;
+            }
+
+            if let Some(ref predicate) = config.custom_filter {
+                if !predicate.test(&read_entry(child_path.clone()).await) {
+                    continue;
+                }
+            }
+
+            child_paths.push(child_path);
+        }
+
+        if let Some(note) = file_limit_note(config, child_paths.len()) {
+            entry.note = Some(note);
+            return entry;
+        }
+
+        let mut children = Vec::with_capacity(child_paths.len());
+        for child_path in child_paths {
+            let mut child_ancestry = ancestry.clone();
+            child_ancestry.include_all |= includes_subtree(config, ancestry.root, &child_path);
+            children.push(walk(child_path, config, stats, current_depth + 1, child_ancestry).await);
+        }
+
+        finish_directory(&mut entry, children, config, stats, current_depth);
+        entry
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::{print_tree, OutputFormat, PrintConfig};
+    use crate::tree::walk_directory;
+
+    fn json(tree: &TreeEntry, stats: &TreeStats) -> String {
+        let config = PrintConfig {
+            output_format: OutputFormat::Json,
+            show_size: true,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, tree, &config, stats).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_async_matches_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src/util")).unwrap();
+        std::fs::create_dir(root.join("empty")).unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("src/util/notes.txt"), "notes").unwrap();
+        std::fs::write(root.join(".hidden"), "").unwrap();
+        std::fs::write(root.join("README.md"), "# readme").unwrap();
+        std::os::unix::fs::symlink("README.md", root.join("link")).unwrap();
+
+        let configs = [
+            TreeConfig::default(),
+            TreeConfig {
+                show_hidden: true,
+                prune_empty: true,
+                max_depth: Some(2),
+                ..TreeConfig::default()
+            },
+        ];
+        for config in configs {
+            let mut sync_stats = TreeStats::default();
+            let sync_tree = walk_directory(root, &config, &mut sync_stats, 0);
+            let mut async_stats = TreeStats::default();
+            let async_tree = async_walk_directory(root, &config, &mut async_stats, 0).await;
+
+            assert_eq!(
+                json(&async_tree, &async_stats),
+                json(&sync_tree, &sync_stats)
+            );
+            assert_eq!(
+                (async_stats.directories, async_stats.files),
+                (sync_stats.directories, sync_stats.files)
+            );
+            assert_eq!(async_stats.total_size, sync_stats.total_size);
+        }
+    }
+}
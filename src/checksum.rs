@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Digest shown by `--checksum`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChecksumAlgorithm {
+    /// CRC-32 (IEEE), as used by zip and gzip
+    Crc32,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    /// Length of a digest written out in hex
+    pub fn hex_len(self) -> usize {
+        match self {
+            ChecksumAlgorithm::Crc32 => 8,
+            ChecksumAlgorithm::Sha256 => 64,
+        }
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "crc32" => Ok(ChecksumAlgorithm::Crc32),
+            "sha256" | "sha-256" => Ok(ChecksumAlgorithm::Sha256),
+            _ => Err(format!(
+                "unknown checksum '{}' (expected crc32 or sha256)",
+                s
+            )),
+        }
+    }
+}
+
+/// Hex digest of the file at `path`, read in chunks so large files never sit
+/// in memory whole
+pub fn file_checksum(path: &Path, algorithm: ChecksumAlgorithm) -> io::Result<String> {
+    checksum_reader(File::open(path)?, algorithm)
+}
+
+/// Hex digest of everything `reader` yields
+pub fn checksum_reader<R: Read>(mut reader: R, algorithm: ChecksumAlgorithm) -> io::Result<String> {
+    let mut buf = vec![0; 64 * 1024];
+    let mut hasher = Hasher::new(algorithm);
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+/// The running state of one [`ChecksumAlgorithm`]
+enum Hasher {
+    Crc32(crc32fast::Hasher),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Crc32(crc) => crc.update(data),
+            Hasher::Sha256(sha) => sha.update(data),
+        }
+    }
+
+    fn finish(self) -> String {
+        match self {
+            Hasher::Crc32(crc) => format!("{:08x}", crc.finalize()),
+            Hasher::Sha256(sha) => sha
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(data: &[u8], algorithm: ChecksumAlgorithm) -> String {
+        checksum_reader(data, algorithm).unwrap()
+    }
+
+    #[test]
+    fn test_known_digests() {
+        let crc32 = ChecksumAlgorithm::Crc32;
+        let sha256 = ChecksumAlgorithm::Sha256;
+        assert_eq!(digest(b"", crc32), "00000000");
+        assert_eq!(digest(b"123456789", crc32), "cbf43926");
+        assert_eq!(
+            digest(b"", sha256),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            digest(b"abc", sha256),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // Two blocks once padded
+        assert_eq!(
+            digest(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                sha256
+            ),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn test_file_checksum_streams() {
+        // Larger than the read buffer, so it's hashed across several reads
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big");
+        std::fs::write(&path, vec![b'a'; 1_000_000]).unwrap();
+        assert_eq!(
+            file_checksum(&path, ChecksumAlgorithm::Sha256).unwrap(),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+        assert_eq!(
+            file_checksum(&path, ChecksumAlgorithm::Crc32).unwrap(),
+            "dc25bfbc"
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::Path;
+
+use colored::{ColoredString, Styles};
+
+use crate::tree::TreeEntry;
+
+/// SGR reset, ending every painted segment
+const RESET: &str = "\x1b[0m";
+
+/// Whether to color the output
+///
+/// `force` (`--color`) wins, then a `NO_COLOR` variable set to anything, even
+/// empty (see no-color.org), then `disable` (`--nocolor`, or color turned off
+/// in a config file); otherwise color goes only to a terminal.
+pub fn use_color(force: bool, no_color: Option<&OsStr>, disable: bool, to_tty: bool) -> bool {
+    if force {
+        true
+    } else if no_color.is_some() || disable {
+        false
+    } else {
+        to_tty
+    }
+}
+
+/// Render a `colored` string as one self-contained segment when `colorize`
+/// is set, or as plain text otherwise
+///
+/// `ColoredString`'s own `Display` consults `colored`'s global tty and
+/// environment detection, which can disagree with the caller's `colorize`
+/// (e.g. `--color` into a file); this follows `colorize` alone. The segment
+/// always ends in a reset, so an interrupted line can't leave the terminal
+/// colored.
+pub fn paint_segment(styled: ColoredString, colorize: bool) -> String {
+    if !colorize || styled.is_plain() {
+        return styled.input;
+    }
+
+    // Same code order as `colored`: styles, background, then foreground
+    let style = styled.style;
+    let mut codes: Vec<String> = [
+        (Styles::Bold, "1"),
+        (Styles::Dimmed, "2"),
+        (Styles::Italic, "3"),
+        (Styles::Underline, "4"),
+        (Styles::Blink, "5"),
+        (Styles::Reversed, "7"),
+        (Styles::Hidden, "8"),
+        (Styles::Strikethrough, "9"),
+    ]
+    .into_iter()
+    .filter(|(s, _)| style.contains(*s))
+    .map(|(_, code)| code.to_string())
+    .collect();
+    codes.extend(styled.bgcolor.map(|c| c.to_bg_str().into_owned()));
+    codes.extend(styled.fgcolor.map(|c| c.to_fg_str().into_owned()));
+    sgr_segment(&codes.join(";"), &styled.input)
+}
+
+/// Wrap `text` in the SGR `code` and a reset, restarting the color after any
+/// reset already inside `text`
+fn sgr_segment(code: &str, text: &str) -> String {
+    let start = format!("\x1b[{}m", code);
+    let text = text.replace(RESET, &format!("{}{}", RESET, start));
+    format!("{}{}{}", start, text, RESET)
+}
+
+/// Color lookup table parsed from the `LS_COLORS` environment variable
+#[derive(Debug, Clone, Default)]
+pub struct LsColors {
+    /// File type keys (`di`, `ln`, `ex`, `fi`, ...) mapped to SGR codes
+    types: HashMap<String, String>,
+    /// Lowercased file extensions (from `*.ext=` entries) mapped to SGR codes
+    extensions: HashMap<String, String>,
+}
+
+impl LsColors {
+    /// Load colors from `LS_COLORS`, returning an empty table if it is unset
+    pub fn from_env() -> Self {
+        std::env::var("LS_COLORS")
+            .map(|s| Self::parse(&s))
+            .unwrap_or_default()
+    }
+
+    /// Parse an `LS_COLORS` string (e.g. `di=01;34:*.tar=01;31`)
+    pub fn parse(s: &str) -> Self {
+        let mut colors = Self::default();
+
+        for item in s.split(':') {
+            let Some((key, value)) = item.split_once('=') else {
+                continue;
+            };
+            if key.is_empty() || value.is_empty() {
+                continue;
+            }
+
+            if let Some(ext) = key.strip_prefix("*.") {
+                colors
+                    .extensions
+                    .insert(ext.to_lowercase(), value.to_string());
+            } else if !key.starts_with('*') {
+                colors.types.insert(key.to_string(), value.to_string());
+            }
+        }
+
+        colors
+    }
+
+    /// Look up the SGR code for an entry, if `LS_COLORS` defines one
+    pub fn code_for(&self, entry: &TreeEntry) -> Option<&str> {
+        if entry.is_dir {
+            return self.types.get("di").map(String::as_str);
+        }
+        if entry.is_broken_symlink() {
+            if let Some(code) = self.types.get("or") {
+                return Some(code);
+            }
+        }
+        if entry.is_symlink {
+            return self.types.get("ln").map(String::as_str);
+        }
+        let special = if entry.is_fifo() {
+            Some("pi")
+        } else if entry.is_socket() {
+            Some("so")
+        } else if entry.is_block_device() {
+            Some("bd")
+        } else if entry.is_char_device() {
+            Some("cd")
+        } else {
+            None
+        };
+        if let Some(code) = special.and_then(|key| self.types.get(key)) {
+            return Some(code);
+        }
+        if entry.is_executable() {
+            if let Some(code) = self.types.get("ex") {
+                return Some(code);
+            }
+        }
+
+        Path::new(&entry.name)
+            .extension()
+            .and_then(|ext| self.extensions.get(&ext.to_string_lossy().to_lowercase()))
+            .or_else(|| self.types.get("fi"))
+            .map(String::as_str)
+    }
+
+    /// Wrap `text` in the entry's ANSI color, or return `None` to use defaults
+    pub fn paint(&self, text: &str, entry: &TreeEntry) -> Option<String> {
+        self.code_for(entry).map(|code| sgr_segment(code, text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_use_color_precedence() {
+        let set = Some(OsStr::new("1"));
+        let empty = Some(OsStr::new(""));
+
+        // Left alone, color follows the terminal
+        assert!(use_color(false, None, false, true));
+        assert!(!use_color(false, None, false, false));
+        // NO_COLOR turns it off on a terminal, even when empty
+        assert!(!use_color(false, set, false, true));
+        assert!(!use_color(false, empty, false, true));
+        // So does --nocolor
+        assert!(!use_color(false, None, true, true));
+        // --color beats everything, terminal or not
+        assert!(use_color(true, set, true, false));
+        assert!(use_color(true, None, false, false));
+    }
+
+    const LS_COLORS: &str = "di=01;34:ln=01;36:ex=01;32:fi=00:*.tar=01;31:*.TGZ=01;35";
+
+    #[test]
+    fn test_paint_extension() {
+        let colors = LsColors::parse(LS_COLORS);
+        let entry = TreeEntry::new("archive.tar".into());
+        assert_eq!(
+            colors.paint("archive.tar", &entry).unwrap(),
+            "\x1b[01;31marchive.tar\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_paint_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let colors = LsColors::parse(LS_COLORS);
+        let entry = TreeEntry::new(dir.path().into());
+        assert_eq!(
+            colors.paint("src", &entry).unwrap(),
+            "\x1b[01;34msrc\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_paint_special_files() {
+        let colors = LsColors::parse("pi=40;33:cd=40;33;01:fi=00");
+        let dev_null = TreeEntry::new("/dev/null".into());
+        assert_eq!(
+            colors.paint("null", &dev_null).unwrap(),
+            "\x1b[40;33;01mnull\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_paint_segment_ignores_colored_override() {
+        use colored::Colorize;
+
+        // Whatever `colored` thinks of the terminal, `colorize` decides
+        assert_eq!(
+            paint_segment("src".bold().blue(), true),
+            "\x1b[1;34msrc\x1b[0m"
+        );
+        assert_eq!(paint_segment("src".bold().blue(), false), "src");
+
+        assert_eq!(paint_segment("plain".normal(), true), "plain");
+        // A reset inside the text doesn't end the color early
+        assert_eq!(
+            paint_segment("a\x1b[0mb".red(), true),
+            "\x1b[31ma\x1b[0m\x1b[31mb\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_missing_key_falls_back() {
+        let colors = LsColors::parse("*.tar=01;31");
+        let entry = TreeEntry::new("src".into());
+        assert!(colors.paint("src", &entry).is_none());
+    }
+}
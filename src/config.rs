@@ -0,0 +1,115 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::printer::PrintConfig;
+use crate::tree::TreeConfig;
+
+/// Defaults loaded from a `--config` file, which command-line flags override
+///
+/// The file has optional `tree` and `print` sections with the fields of
+/// [`TreeConfig`] and [`PrintConfig`]; anything left out keeps its default.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub tree: TreeConfig,
+    pub print: PrintConfig,
+}
+
+impl Config {
+    /// Load a config file, read as JSON or YAML for `.json`, `.yaml` and
+    /// `.yml` extensions and as TOML otherwise
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&content).map_err(io::Error::other),
+            Some("yaml" | "yml") => serde_yaml::from_str(&content).map_err(io::Error::other),
+            _ => toml::from_str(&content).map_err(io::Error::other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::{LineCharset, OutputFormat};
+    use crate::sort::{GroupOrder, SortKey};
+
+    #[test]
+    fn test_load_toml() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.toml");
+        fs::write(
+            &path,
+            r#"
+[tree]
+show_hidden = true
+max_depth = 3
+sort_key = "mtime"
+group_order = "dirs-first"
+
+[tree.filter]
+include = ["*.{rs,toml}"]
+exclude = ["target"]
+min_size = 1024
+
+[print]
+show_size = true
+output_format = "json"
+charset = "ascii"
+"#,
+        )
+        .unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert!(config.tree.show_hidden);
+        assert_eq!(config.tree.max_depth, Some(3));
+        assert!(matches!(config.tree.sort_key, SortKey::Time));
+        assert_eq!(config.tree.group_order, GroupOrder::DirsFirst);
+        assert_eq!(config.tree.filter.include_patterns.len(), 2);
+        assert_eq!(config.tree.filter.min_size, Some(1024));
+        assert!(!config
+            .tree
+            .filter
+            .matches("target", Path::new("target"), true));
+        assert!(config.print.show_size);
+        assert!(matches!(config.print.output_format, OutputFormat::Json));
+        assert_eq!(config.print.charset, LineCharset::Ascii);
+        // Unset fields keep their defaults
+        assert!(!config.tree.dirs_only);
+        assert_eq!(config.print.indent_width, 4);
+    }
+
+    #[test]
+    fn test_load_json_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.json");
+        fs::write(
+            &path,
+            r#"{"tree": {"filter": {"exclude": ["*.log"], "newer_than": "2024-01-15"}}}"#,
+        )
+        .unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert!(config.tree.filter.newer_than.is_some());
+
+        let json = serde_json::to_string(&config).unwrap();
+        let reloaded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(reloaded.tree.filter.exclude_patterns.len(), 1);
+        assert_eq!(
+            reloaded.tree.filter.newer_than,
+            config.tree.filter.newer_than
+        );
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.toml");
+        fs::write(&path, "[tree.filter]\ninclude = [\"[\"]\n").unwrap();
+        let err = Config::load(&path).unwrap_err();
+        assert!(err.to_string().contains("invalid pattern"));
+    }
+}
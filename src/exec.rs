@@ -0,0 +1,148 @@
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+use crate::tree::TreeEntry;
+
+/// A command to run per listed file (`--exec`), like `find -exec`
+///
+/// The template is split on whitespace, without a shell, and every `{}` in
+/// it is replaced by the file's path. A template without `{}` gets the path
+/// appended as its last argument.
+#[derive(Debug, Clone)]
+pub struct ExecTemplate {
+    words: Vec<String>,
+}
+
+impl FromStr for ExecTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words: Vec<String> = s.split_whitespace().map(str::to_string).collect();
+        if words.is_empty() {
+            return Err("empty command".to_string());
+        }
+        if !words.iter().any(|word| word.contains("{}")) {
+            words.push("{}".to_string());
+        }
+        Ok(Self { words })
+    }
+}
+
+impl ExecTemplate {
+    /// The arguments for `path`, program first
+    fn args(&self, path: &Path) -> Vec<OsString> {
+        let path = path.as_os_str();
+        self.words
+            .iter()
+            .map(|word| {
+                let mut parts = word.split("{}");
+                let mut arg = OsString::from(parts.next().unwrap_or_default());
+                for part in parts {
+                    arg.push(path);
+                    arg.push(part);
+                }
+                arg
+            })
+            .collect()
+    }
+
+    /// The command line for `path`, as shown by `--exec-dry-run`
+    pub fn render(&self, path: &Path) -> String {
+        self.args(path)
+            .iter()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Run the command for `path`, waiting for it to finish; its output goes
+    /// straight to ours
+    pub fn run(&self, path: &Path) -> Result<(), String> {
+        let args = self.args(path);
+        let status = Command::new(&args[0])
+            .args(&args[1..])
+            .status()
+            .map_err(|e| format!("{}: {}", self.render(path), e))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("{}: {}", self.render(path), status))
+        }
+    }
+}
+
+/// Files below `entry` that `--exec` runs on: the listed regular files, in
+/// display order; symlinks, broken or not, and special files are skipped
+pub fn exec_targets(entry: &TreeEntry) -> impl Iterator<Item = &TreeEntry> {
+    entry
+        .iter()
+        .filter(|(depth, e)| {
+            *depth > 0 && !e.is_symlink && e.metadata.as_ref().is_some_and(|m| m.is_file())
+        })
+        .map(|(_, e)| e)
+}
+
+/// Run `template` for each file below `entry`, carrying on past failures
+///
+/// Returns how many commands ran and the errors of those that couldn't start
+/// or exited unsuccessfully.
+pub fn exec_files(entry: &TreeEntry, template: &ExecTemplate) -> (usize, Vec<String>) {
+    let mut ran = 0;
+    let mut errors = Vec::new();
+    for file in exec_targets(entry) {
+        ran += 1;
+        if let Err(e) = template.run(&file.path) {
+            errors.push(e);
+        }
+    }
+    (ran, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{walk_directory, TreeConfig, TreeStats};
+
+    #[test]
+    fn test_render_template() {
+        let template: ExecTemplate = "gzip -k {}".parse().unwrap();
+        assert_eq!(template.render(Path::new("a b.log")), "gzip -k a b.log");
+        assert_eq!(
+            template.args(Path::new("a b.log")),
+            vec!["gzip", "-k", "a b.log"]
+        );
+
+        let template: ExecTemplate = "cp {} {}.bak".parse().unwrap();
+        assert_eq!(template.render(Path::new("x")), "cp x x.bak");
+        let template: ExecTemplate = "wc -l".parse().unwrap();
+        assert_eq!(template.render(Path::new("x")), "wc -l x");
+        assert!("  ".parse::<ExecTemplate>().is_err());
+    }
+
+    #[test]
+    fn test_exec_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("a.log"), "").unwrap();
+        std::fs::write(root.join("sub/b.log"), "").unwrap();
+        std::fs::write(root.join("sub/c.log"), "").unwrap();
+        std::os::unix::fs::symlink("a.log", root.join("link")).unwrap();
+        std::os::unix::fs::symlink("missing", root.join("broken")).unwrap();
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+
+        // Directories and symlinks are skipped
+        let (ran, errors) = exec_files(&tree, &"echo {}".parse().unwrap());
+        assert_eq!((ran, errors.len()), (3, 0));
+
+        // Failures are collected without stopping the rest
+        let (ran, errors) = exec_files(&tree, &"false {}".parse().unwrap());
+        assert_eq!((ran, errors.len()), (3, 3));
+        let (ran, errors) = exec_files(&tree, &"no-such-command-here".parse().unwrap());
+        assert_eq!((ran, errors.len()), (3, 3));
+        assert!(errors[0].starts_with("no-such-command-here "));
+    }
+}
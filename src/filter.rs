@@ -1,14 +1,216 @@
-use glob::Pattern;
+use std::fmt;
+use std::fs::Metadata;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local};
+use glob::{MatchOptions, Pattern};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::format::parse_time_bound;
+
+/// An include or exclude pattern that failed to compile
+#[derive(Debug)]
+pub enum PatternError {
+    Glob(glob::PatternError),
+    Regex(regex::Error),
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternError::Glob(e) => write!(f, "{}", e),
+            PatternError::Regex(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+/// A `find -perm` style test on a file's permission bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PermMatch {
+    /// `0644`: the mode is exactly these bits
+    Exact(u32),
+    /// `-0002`: every one of these bits is set
+    AllSet(u32),
+    /// `/0002`: at least one of these bits is set
+    AnySet(u32),
+}
+
+impl PermMatch {
+    /// Test a mode; only the permission, setuid, setgid and sticky bits count
+    pub fn matches(self, mode: u32) -> bool {
+        let mode = mode & 0o7777;
+        match self {
+            PermMatch::Exact(bits) => mode == bits,
+            PermMatch::AllSet(bits) => mode & bits == bits,
+            // As with find, no bits at all matches everything
+            PermMatch::AnySet(bits) => bits == 0 || mode & bits != 0,
+        }
+    }
+}
+
+impl FromStr for PermMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (make, digits): (fn(u32) -> PermMatch, _) = match s.as_bytes().first() {
+            Some(b'-') => (PermMatch::AllSet, &s[1..]),
+            Some(b'/') => (PermMatch::AnySet, &s[1..]),
+            _ => (PermMatch::Exact, s),
+        };
+        // from_str_radix would also take a sign, as in `+777` or `-+7`
+        let octal = !digits.is_empty() && digits.bytes().all(|b| (b'0'..=b'7').contains(&b));
+        match u32::from_str_radix(digits, 8).ok().filter(|_| octal) {
+            Some(bits) if bits <= 0o7777 => Ok(make(bits)),
+            _ => Err(format!("invalid mode '{}', expected octal like 0644", s)),
+        }
+    }
+}
+
+impl fmt::Display for PermMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PermMatch::Exact(bits) => write!(f, "{:04o}", bits),
+            PermMatch::AllSet(bits) => write!(f, "-{:04o}", bits),
+            PermMatch::AnySet(bits) => write!(f, "/{:04o}", bits),
+        }
+    }
+}
+
+/// How `-P` and `-I` patterns are compared against names
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchMode {
+    /// Shell-style wildcards
+    #[default]
+    Glob,
+    /// The whole name, literally
+    Exact,
+    /// The start of the name
+    Prefix,
+    /// The end of the name
+    Suffix,
+    /// Anywhere in the name
+    Contains,
+}
+
+impl MatchMode {
+    /// Test a literal pattern against a name
+    fn matches_literal(self, pattern: &str, name: &str) -> bool {
+        match self {
+            MatchMode::Glob | MatchMode::Exact => name == pattern,
+            MatchMode::Prefix => name.starts_with(pattern),
+            MatchMode::Suffix => name.ends_with(pattern),
+            MatchMode::Contains => name.contains(pattern),
+        }
+    }
+}
+
+impl FromStr for MatchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "glob" => Ok(MatchMode::Glob),
+            "exact" => Ok(MatchMode::Exact),
+            "prefix" => Ok(MatchMode::Prefix),
+            "suffix" => Ok(MatchMode::Suffix),
+            "contains" => Ok(MatchMode::Contains),
+            _ => Err(format!(
+                "unknown match mode '{}' (expected glob, exact, prefix, suffix or contains)",
+                s
+            )),
+        }
+    }
+}
+
+/// How several include patterns combine
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PatternMode {
+    /// An entry is listed if it matches any include pattern
+    #[default]
+    Any,
+    /// An entry is listed only if it matches every include pattern
+    All,
+}
+
+impl FromStr for PatternMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(PatternMode::Any),
+            "all" => Ok(PatternMode::All),
+            _ => Err(format!(
+                "unknown pattern mode '{}' (expected any or all)",
+                s
+            )),
+        }
+    }
+}
+
+/// Full-path matching keeps `*` within one path component; only `**` crosses
+/// directory boundaries
+const PATH_MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
 
 /// Filter configuration for file matching
-#[derive(Debug, Clone, Default)]
+///
+/// (De)serializes through [`FilterSpec`], with patterns as strings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "FilterSpec", into = "FilterSpec")]
 pub struct Filter {
     /// Patterns to include (empty means include all)
     pub include_patterns: Vec<Pattern>,
+    /// How many of `include_patterns` each include pattern expanded to, in
+    /// order, so brace alternatives count as one pattern under
+    /// `PatternMode::All`; globs beyond these each stand alone
+    pub include_glob_groups: Vec<usize>,
     /// Patterns to exclude
     pub exclude_patterns: Vec<Pattern>,
+    /// Regex patterns to include, used instead of globs with `use_regex`
+    pub include_regexes: Vec<Regex>,
+    /// Regex patterns to exclude, used instead of globs with `use_regex`
+    pub exclude_regexes: Vec<Regex>,
+    /// Literal patterns to include, used instead of globs outside `MatchMode::Glob`
+    pub include_literals: Vec<String>,
+    /// Literal patterns to exclude, used instead of globs outside `MatchMode::Glob`
+    pub exclude_literals: Vec<String>,
+    /// Treat patterns as regular expressions rather than globs
+    pub use_regex: bool,
+    /// How non-regex patterns are compared against names
+    pub match_mode: MatchMode,
+    /// Whether an entry must match any or all include patterns
+    pub pattern_mode: PatternMode,
     /// Whether pattern matching is case-insensitive
     pub ignore_case: bool,
+    /// Match patterns against the path relative to the walk root instead of
+    /// just the file name
+    pub match_full_path: bool,
+    /// A directory matching an include pattern lists its whole subtree
+    pub match_dirs: bool,
+    /// Apply include patterns to files only, so every directory is descended
+    /// into and only the files in it are filtered
+    pub include_files_only: bool,
+    /// Minimum file size in bytes
+    pub min_size: Option<u64>,
+    /// Maximum file size in bytes
+    pub max_size: Option<u64>,
+    /// Only files modified after this time
+    pub newer_than: Option<SystemTime>,
+    /// Only files modified before this time
+    pub older_than: Option<SystemTime>,
+    /// Only files whose permission bits pass this test
+    pub perm: Option<PermMatch>,
 }
 
 impl Filter {
@@ -17,52 +219,774 @@ impl Filter {
     }
 
     /// Add an include pattern (-P)
-    pub fn add_include(&mut self, pattern: &str) -> Result<(), glob::PatternError> {
-        let pattern_str = if self.ignore_case {
-            pattern.to_lowercase()
+    ///
+    /// Like ripgrep's globs, a leading `!` makes it an exclude pattern
+    /// instead; `\!` stands for a literal `!`.
+    pub fn add_include(&mut self, pattern: &str) -> Result<(), PatternError> {
+        if let Some(negated) = pattern.strip_prefix('!') {
+            return self.add_exclude(negated);
+        }
+        let pattern = if pattern.starts_with("\\!") {
+            &pattern[1..]
         } else {
-            pattern.to_string()
+            pattern
         };
-        self.include_patterns.push(Pattern::new(&pattern_str)?);
+
+        if self.use_regex {
+            self.include_regexes.push(self.compile_regex(pattern)?);
+        } else if self.match_mode != MatchMode::Glob {
+            self.include_literals.push(self.literal(pattern));
+        } else {
+            let globs = self.compile_globs(pattern)?;
+            self.include_glob_groups.push(globs.len());
+            self.include_patterns.extend(globs);
+        }
         Ok(())
     }
 
     /// Add an exclude pattern (-I)
-    pub fn add_exclude(&mut self, pattern: &str) -> Result<(), glob::PatternError> {
-        let pattern_str = if self.ignore_case {
+    pub fn add_exclude(&mut self, pattern: &str) -> Result<(), PatternError> {
+        if self.use_regex {
+            self.exclude_regexes.push(self.compile_regex(pattern)?);
+        } else if self.match_mode != MatchMode::Glob {
+            self.exclude_literals.push(self.literal(pattern));
+        } else {
+            self.exclude_patterns.extend(self.compile_globs(pattern)?);
+        }
+        Ok(())
+    }
+
+    /// Add an exclude pattern for each line of an ignore file, skipping
+    /// blank lines and `#` comments
+    ///
+    /// On failure, returns the 1-based line number of the bad pattern.
+    pub fn add_excludes_from(&mut self, content: &str) -> Result<(), (usize, PatternError)> {
+        for (idx, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            self.add_exclude(line).map_err(|e| (idx + 1, e))?;
+        }
+        Ok(())
+    }
+
+    /// Compile a glob, first expanding `{a,b}` alternatives into one pattern
+    /// each since `glob` has no brace syntax
+    fn compile_globs(&self, pattern: &str) -> Result<Vec<Pattern>, PatternError> {
+        expand_braces(pattern)
+            .iter()
+            .map(|pattern| {
+                let pattern_str = if self.ignore_case {
+                    pattern.to_lowercase()
+                } else {
+                    pattern.to_string()
+                };
+                Pattern::new(&pattern_str).map_err(PatternError::Glob)
+            })
+            .collect()
+    }
+
+    /// A pattern kept as plain text, folded to lowercase like the names it's
+    /// compared against when ignoring case
+    fn literal(&self, pattern: &str) -> String {
+        if self.ignore_case {
             pattern.to_lowercase()
         } else {
             pattern.to_string()
-        };
-        self.exclude_patterns.push(Pattern::new(&pattern_str)?);
-        Ok(())
+        }
     }
 
-    /// Check if a filename matches the filter criteria
-    pub fn matches(&self, name: &str, _is_dir: bool) -> bool {
-        let match_name = if self.ignore_case {
-            name.to_lowercase()
+    fn compile_regex(&self, pattern: &str) -> Result<Regex, PatternError> {
+        RegexBuilder::new(pattern)
+            .case_insensitive(self.ignore_case)
+            .build()
+            .map_err(PatternError::Regex)
+    }
+
+    /// Check if a file matches the filter criteria
+    ///
+    /// Patterns see `name` unless `match_full_path` is set, in which case they
+    /// see `relative`, the path from the walk root. Exclude patterns apply to
+    /// every entry, and so do include patterns unless `include_files_only` is
+    /// set, in which case directories always pass them.
+    pub fn matches(&self, name: &str, relative: &Path, is_dir: bool) -> bool {
+        !self.is_excluded(name, relative)
+            && ((is_dir && self.include_files_only) || self.is_included(name, relative))
+    }
+
+    /// Whether an exclude pattern matches
+    pub fn is_excluded(&self, name: &str, relative: &Path) -> bool {
+        self.any_match(
+            name,
+            relative,
+            &self.exclude_patterns,
+            &self.exclude_regexes,
+            &self.exclude_literals,
+        )
+    }
+
+    /// Whether an include pattern matches (every one under
+    /// `PatternMode::All`), or there are none
+    pub fn is_included(&self, name: &str, relative: &Path) -> bool {
+        let has_includes = !self.include_patterns.is_empty()
+            || !self.include_regexes.is_empty()
+            || !self.include_literals.is_empty();
+        if !has_includes {
+            return true;
+        }
+        match self.pattern_mode {
+            PatternMode::Any => self.any_match(
+                name,
+                relative,
+                &self.include_patterns,
+                &self.include_regexes,
+                &self.include_literals,
+            ),
+            PatternMode::All => self.all_includes_match(name, relative),
+        }
+    }
+
+    /// Whether a directory matched an include pattern under `match_dirs`, so
+    /// everything beneath it is listed regardless of include patterns
+    pub fn includes_dir(&self, name: &str, relative: &Path) -> bool {
+        self.match_dirs && self.is_included(name, relative)
+    }
+
+    fn any_match(
+        &self,
+        name: &str,
+        relative: &Path,
+        globs: &[Pattern],
+        regexes: &[Regex],
+        literals: &[String],
+    ) -> bool {
+        let (subject, match_name) = self.subject(name, relative);
+
+        globs.iter().any(|p| self.glob_matches(p, &match_name))
+            || regexes.iter().any(|r| r.is_match(&subject))
+            || literals
+                .iter()
+                .any(|l| self.match_mode.matches_literal(l, &match_name))
+    }
+
+    fn all_includes_match(&self, name: &str, relative: &Path) -> bool {
+        let (subject, match_name) = self.subject(name, relative);
+
+        // A brace pattern matches if any of its alternatives does
+        let mut globs = &self.include_patterns[..];
+        for &count in &self.include_glob_groups {
+            let (group, rest) = globs.split_at(count.min(globs.len()));
+            if !group.iter().any(|p| self.glob_matches(p, &match_name)) {
+                return false;
+            }
+            globs = rest;
+        }
+
+        globs.iter().all(|p| self.glob_matches(p, &match_name))
+            && self.include_regexes.iter().all(|r| r.is_match(&subject))
+            && self
+                .include_literals
+                .iter()
+                .all(|l| self.match_mode.matches_literal(l, &match_name))
+    }
+
+    /// The text patterns are tested against, the name or the path from the
+    /// root, along with the lowercased form globs and literals see when
+    /// ignoring case
+    fn subject(&self, name: &str, relative: &Path) -> (String, String) {
+        let subject = if self.match_full_path {
+            relative.to_string_lossy().to_string()
         } else {
             name.to_string()
         };
+        let match_name = if self.ignore_case {
+            subject.to_lowercase()
+        } else {
+            subject.clone()
+        };
+        (subject, match_name)
+    }
+
+    fn glob_matches(&self, pattern: &Pattern, match_name: &str) -> bool {
+        if self.match_full_path {
+            pattern.matches_with(match_name, PATH_MATCH_OPTIONS)
+        } else {
+            pattern.matches(match_name)
+        }
+    }
+
+    /// Whether `matches_metadata` needs to be consulted at all
+    pub fn needs_metadata(&self) -> bool {
+        self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.newer_than.is_some()
+            || self.older_than.is_some()
+            || self.perm.is_some()
+    }
+
+    /// Check a file's metadata against the size, modification time and
+    /// permission filters
+    ///
+    /// Directories are never filtered here so matching files beneath them
+    /// stay reachable.
+    pub fn matches_metadata(&self, metadata: &Metadata) -> bool {
+        if metadata.is_dir() {
+            return true;
+        }
 
-        // Check exclude patterns first
-        for pattern in &self.exclude_patterns {
-            if pattern.matches(&match_name) {
+        let size = metadata.len();
+        if self.min_size.is_some_and(|min| size < min) {
+            return false;
+        }
+        if self.max_size.is_some_and(|max| size > max) {
+            return false;
+        }
+
+        if self.newer_than.is_some() || self.older_than.is_some() {
+            let Ok(modified) = metadata.modified() else {
+                return false;
+            };
+            if self.newer_than.is_some_and(|newer| modified <= newer) {
                 return false;
             }
+            if self.older_than.is_some_and(|older| modified >= older) {
+                return false;
+            }
+        }
+
+        if let Some(perm) = self.perm {
+            return perm.matches(metadata.permissions().mode());
+        }
+
+        true
+    }
+}
+
+/// Serializable form of a [`Filter`], as written in config files
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilterSpec {
+    /// Patterns to include (`-P`)
+    pub include: Vec<String>,
+    /// Patterns to exclude (`-I`)
+    pub exclude: Vec<String>,
+    pub ignore_case: bool,
+    pub use_regex: bool,
+    pub match_mode: MatchMode,
+    pub pattern_mode: PatternMode,
+    pub match_full_path: bool,
+    pub match_dirs: bool,
+    pub include_files_only: bool,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Any time accepted by `--newer-than`, e.g. `2024-01-15` or `7d`
+    pub newer_than: Option<String>,
+    pub older_than: Option<String>,
+    /// Any mode accepted by `--same-permissions`, e.g. `0644`, `-0002` or `/0111`
+    pub perm: Option<String>,
+}
+
+impl TryFrom<FilterSpec> for Filter {
+    type Error = String;
+
+    fn try_from(spec: FilterSpec) -> Result<Self, Self::Error> {
+        let mut filter = Filter {
+            ignore_case: spec.ignore_case,
+            use_regex: spec.use_regex,
+            match_mode: spec.match_mode,
+            pattern_mode: spec.pattern_mode,
+            match_full_path: spec.match_full_path,
+            match_dirs: spec.match_dirs,
+            include_files_only: spec.include_files_only,
+            min_size: spec.min_size,
+            max_size: spec.max_size,
+            newer_than: spec
+                .newer_than
+                .as_deref()
+                .map(parse_time_bound)
+                .transpose()?,
+            older_than: spec
+                .older_than
+                .as_deref()
+                .map(parse_time_bound)
+                .transpose()?,
+            perm: spec.perm.as_deref().map(str::parse).transpose()?,
+            ..Filter::default()
+        };
+        for pattern in &spec.include {
+            filter
+                .add_include(pattern)
+                .map_err(|e| format!("invalid pattern '{}': {}", pattern, e))?;
+        }
+        for pattern in &spec.exclude {
+            filter
+                .add_exclude(pattern)
+                .map_err(|e| format!("invalid ignore pattern '{}': {}", pattern, e))?;
         }
+        Ok(filter)
+    }
+}
+
+impl From<Filter> for FilterSpec {
+    fn from(filter: Filter) -> Self {
+        let patterns = |globs: &[Pattern], regexes: &[Regex], literals: &[String]| {
+            globs
+                .iter()
+                .map(|p| p.as_str().to_string())
+                .chain(regexes.iter().map(|r| r.as_str().to_string()))
+                .chain(literals.iter().cloned())
+                .collect()
+        };
+        let time = |t: SystemTime| DateTime::<Local>::from(t).to_rfc3339();
 
-        // Check include patterns (if any are specified)
-        if !self.include_patterns.is_empty() {
-            for pattern in &self.include_patterns {
-                if pattern.matches(&match_name) {
-                    return true;
+        // Rejoin brace alternatives so each include pattern reloads as one
+        let mut include_globs = Vec::new();
+        let mut globs = &filter.include_patterns[..];
+        for &count in &filter.include_glob_groups {
+            let (group, rest) = globs.split_at(count.min(globs.len()));
+            include_globs.push(join_alternatives(group));
+            globs = rest;
+        }
+        include_globs.extend(globs.iter().map(|p| p.as_str().to_string()));
+
+        // Re-escape a leading `!` so it doesn't reload as an exclude
+        let include = include_globs
+            .into_iter()
+            .chain(patterns(
+                &[],
+                &filter.include_regexes,
+                &filter.include_literals,
+            ))
+            .map(|p: String| {
+                if p.starts_with('!') {
+                    format!("\\{}", p)
+                } else {
+                    p
+                }
+            })
+            .collect();
+
+        FilterSpec {
+            include,
+            exclude: patterns(
+                &filter.exclude_patterns,
+                &filter.exclude_regexes,
+                &filter.exclude_literals,
+            ),
+            ignore_case: filter.ignore_case,
+            use_regex: filter.use_regex,
+            match_mode: filter.match_mode,
+            pattern_mode: filter.pattern_mode,
+            match_full_path: filter.match_full_path,
+            match_dirs: filter.match_dirs,
+            include_files_only: filter.include_files_only,
+            min_size: filter.min_size,
+            max_size: filter.max_size,
+            newer_than: filter.newer_than.map(time),
+            older_than: filter.older_than.map(time),
+            perm: filter.perm.map(|perm| perm.to_string()),
+        }
+    }
+}
+
+/// Expand shell-style brace alternation: `*.{rs,toml}` becomes `*.rs` and
+/// `*.toml`
+///
+/// Groups may nest, and `\{`, `\}` and `\,` stand for the literal characters.
+/// Braces without a comma inside, like `{a}`, are kept as-is.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    match find_brace_group(pattern) {
+        Some((open, close, alternatives)) => alternatives
+            .iter()
+            .flat_map(|alternative| {
+                expand_braces(&format!(
+                    "{}{}{}",
+                    &pattern[..open],
+                    alternative,
+                    &pattern[close + 1..]
+                ))
+            })
+            .collect(),
+        None => vec![unescape_braces(pattern)],
+    }
+}
+
+/// Find the first `{...}` group with a top-level comma, returning the
+/// positions of its braces and its alternatives (still escaped)
+fn find_brace_group(pattern: &str) -> Option<(usize, usize, Vec<&str>)> {
+    let bytes = pattern.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'{' => {
+                if let Some((close, commas)) = match_brace(bytes, i) {
+                    if !commas.is_empty() {
+                        let mut alternatives = Vec::new();
+                        let mut start = i + 1;
+                        for comma in commas.into_iter().chain([close]) {
+                            alternatives.push(&pattern[start..comma]);
+                            start = comma + 1;
+                        }
+                        return Some((i, close, alternatives));
+                    }
                 }
             }
-            return false;
+            _ => {}
         }
+        i += 1;
+    }
+    None
+}
 
-        true
+/// Find the `}` matching the `{` at `open`, along with the positions of
+/// commas directly inside it
+fn match_brace(bytes: &[u8], open: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut i = open + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return Some((i, commas)),
+            b'}' => depth -= 1,
+            b',' if depth == 0 => commas.push(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// The inverse of [`expand_braces`] for one pattern's expansions: a lone
+/// pattern as-is, several as one `{a,b}` group, with literal braces and
+/// commas escaped again
+fn join_alternatives(globs: &[Pattern]) -> String {
+    let escaped: Vec<String> = globs
+        .iter()
+        .map(|glob| {
+            glob.as_str()
+                .replace('{', "\\{")
+                .replace('}', "\\}")
+                .replace(',', "\\,")
+        })
+        .collect();
+    match &escaped[..] {
+        [glob] => glob.clone(),
+        _ => format!("{{{}}}", escaped.join(",")),
+    }
+}
+
+fn unescape_braces(pattern: &str) -> String {
+    pattern
+        .replace("\\{", "{")
+        .replace("\\}", "}")
+        .replace("\\,", ",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perm_match() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mode_of = |name: &str, mode: u32| {
+            let path = dir.path().join(name);
+            fs::write(&path, "").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+            fs::metadata(&path).unwrap()
+        };
+        let open = mode_of("open", 0o777);
+        let shared = mode_of("shared", 0o664);
+        let private = mode_of("private", 0o600);
+
+        let matching = |perm: &str| {
+            let filter = Filter {
+                perm: Some(perm.parse().unwrap()),
+                ..Filter::default()
+            };
+            [&open, &shared, &private].map(|m| filter.matches_metadata(m))
+        };
+        assert_eq!(matching("0777"), [true, false, false]);
+        assert_eq!(matching("600"), [false, false, true]);
+        assert_eq!(matching("-0002"), [true, false, false]);
+        assert_eq!(matching("-0600"), [true, true, true]);
+        assert_eq!(matching("/0022"), [true, true, false]);
+        assert_eq!(matching("/0111"), [true, false, false]);
+
+        assert!("0778".parse::<PermMatch>().is_err());
+        assert!("-17777".parse::<PermMatch>().is_err());
+        for bad in ["", "-", "/", "+777", "-+7", "/+1", " 644"] {
+            assert!(bad.parse::<PermMatch>().is_err(), "{:?}", bad);
+        }
+        assert_eq!("-2".parse::<PermMatch>().unwrap().to_string(), "-0002");
+    }
+
+    #[test]
+    fn test_match_dirs_include() {
+        let mut filter = Filter::new();
+        filter.add_include("src*").unwrap();
+        let relative = Path::new("src");
+
+        // Directories pass include patterns only when those apply to files alone
+        assert!(filter.matches("src", relative, true));
+        assert!(!filter.matches("lib", Path::new("lib"), true));
+        filter.include_files_only = true;
+        assert!(filter.matches("lib", Path::new("lib"), true));
+        assert!(!filter.includes_dir("src", relative));
+
+        filter.match_dirs = true;
+        assert!(filter.includes_dir("src", relative));
+        assert!(!filter.includes_dir("lib", Path::new("lib")));
+    }
+
+    #[test]
+    fn test_pattern_modes() {
+        let names = ["test_walk.rs", "walk.rs", "test_notes.md", "Cargo.toml"];
+        let included = |mode: PatternMode, patterns: &[&str]| {
+            let mut filter = Filter {
+                pattern_mode: mode,
+                ..Filter::default()
+            };
+            for pattern in patterns {
+                filter.add_include(pattern).unwrap();
+            }
+            names
+                .into_iter()
+                .filter(|name| filter.matches(name, Path::new(name), false))
+                .collect::<Vec<_>>()
+        };
+
+        let patterns = ["*test*", "*.rs"];
+        assert_eq!(
+            included(PatternMode::Any, &patterns),
+            ["test_walk.rs", "walk.rs", "test_notes.md"]
+        );
+        assert_eq!(included(PatternMode::All, &patterns), ["test_walk.rs"]);
+
+        // Brace alternatives are one pattern, not several to satisfy at once
+        let patterns = ["test_*", "*.{rs,md}"];
+        assert_eq!(
+            included(PatternMode::All, &patterns),
+            ["test_walk.rs", "test_notes.md"]
+        );
+        assert_eq!(
+            included(PatternMode::All, &["*.{rs,toml}"]),
+            ["test_walk.rs", "walk.rs", "Cargo.toml"]
+        );
+
+        // Directories stay traversable whatever the mode
+        let mut filter = Filter {
+            pattern_mode: PatternMode::All,
+            include_files_only: true,
+            ..Filter::default()
+        };
+        filter.add_include("*test*").unwrap();
+        filter.add_include("*.rs").unwrap();
+        assert!(filter.matches("src", Path::new("src"), true));
+        assert!("every".parse::<PatternMode>().is_err());
+    }
+
+    #[test]
+    fn test_pattern_groups_round_trip() {
+        let mut filter = Filter {
+            pattern_mode: PatternMode::All,
+            ..Filter::default()
+        };
+        filter.add_include("test_*").unwrap();
+        filter.add_include("*.{rs,md}").unwrap();
+        filter.add_include("*\\{a\\,b\\}*").unwrap();
+
+        let spec = FilterSpec::from(filter);
+        assert_eq!(spec.include, ["test_*", "{*.rs,*.md}", "*\\{a\\,b\\}*"]);
+        let reloaded = Filter::try_from(spec).unwrap();
+        assert_eq!(reloaded.include_glob_groups, [1, 2, 1]);
+        let matches = |name: &str| reloaded.matches(name, Path::new(name), false);
+        assert!(matches("test_{a,b}.md"));
+        assert!(!matches("test_{a,b}.toml"));
+        assert!(!matches("test_a.md"));
+    }
+
+    #[test]
+    fn test_negated_include() {
+        let mut filter = Filter::new();
+        filter.add_include("*.rs").unwrap();
+        filter.add_include("!test_*").unwrap();
+        let matches = |filter: &Filter, name: &str| filter.matches(name, Path::new(name), false);
+        assert!(matches(&filter, "main.rs"));
+        assert!(!matches(&filter, "test_main.rs"));
+        assert!(!matches(&filter, "notes.md"));
+        assert_eq!(filter.include_patterns.len(), 1);
+        assert_eq!(filter.exclude_patterns.len(), 1);
+
+        // A lone negation excludes without limiting what else is listed
+        let mut filter = Filter::new();
+        filter.add_include("!*.tmp").unwrap();
+        assert!(matches(&filter, "notes.md"));
+        assert!(!matches(&filter, "scratch.tmp"));
+
+        // An escaped `!` is part of the name, and stays escaped in a spec
+        let mut filter = Filter::new();
+        filter.add_include("\\!important*").unwrap();
+        assert!(matches(&filter, "!important.txt"));
+        assert!(!matches(&filter, "important.txt"));
+        let spec = FilterSpec::from(filter);
+        assert_eq!(spec.include, ["\\!important*"]);
+        let reloaded = Filter::try_from(spec).unwrap();
+        assert!(matches(&reloaded, "!important.txt"));
+        assert!(!matches(&reloaded, "notes.md"));
+    }
+
+    #[test]
+    fn test_match_modes() {
+        let names = ["main.rs", "main.rs.bak", "domain.rs", "Main.RS", "*.rs"];
+        let included = |mode: MatchMode, ignore_case: bool, pattern: &str| {
+            let mut filter = Filter {
+                match_mode: mode,
+                ignore_case,
+                ..Filter::default()
+            };
+            filter.add_include(pattern).unwrap();
+            names
+                .into_iter()
+                .filter(|name| filter.matches(name, Path::new(name), false))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            included(MatchMode::Glob, false, "*.rs"),
+            ["main.rs", "domain.rs", "*.rs"]
+        );
+        assert_eq!(included(MatchMode::Exact, false, "*.rs"), ["*.rs"]);
+        assert_eq!(included(MatchMode::Exact, false, "main.rs"), ["main.rs"]);
+        assert_eq!(
+            included(MatchMode::Exact, true, "main.rs"),
+            ["main.rs", "Main.RS"]
+        );
+        assert_eq!(
+            included(MatchMode::Prefix, false, "main"),
+            ["main.rs", "main.rs.bak"]
+        );
+        assert_eq!(
+            included(MatchMode::Suffix, false, ".rs"),
+            ["main.rs", "domain.rs", "*.rs"]
+        );
+        assert_eq!(
+            included(MatchMode::Contains, false, "main"),
+            ["main.rs", "main.rs.bak", "domain.rs"]
+        );
+        assert_eq!(
+            included(MatchMode::Contains, true, "MAIN"),
+            ["main.rs", "main.rs.bak", "domain.rs", "Main.RS"]
+        );
+
+        // Brackets aren't glob syntax outside glob mode, so they can't fail
+        let mut filter = Filter {
+            match_mode: MatchMode::Suffix,
+            ..Filter::default()
+        };
+        filter.add_exclude("[").unwrap();
+        assert!(filter.is_excluded("a[", Path::new("a[")));
+        assert!("substring".parse::<MatchMode>().is_err());
+    }
+
+    #[test]
+    fn test_regex_include() {
+        let mut filter = Filter {
+            use_regex: true,
+            ..Filter::default()
+        };
+        filter.add_include(r"^\d").unwrap();
+
+        assert!(filter.matches("1-intro.md", Path::new("1-intro.md"), false));
+        assert!(!filter.matches("intro.md", Path::new("intro.md"), false));
+        assert!(!filter.matches("docs", Path::new("docs"), true));
+        filter.include_files_only = true;
+        assert!(filter.matches("docs", Path::new("docs"), true));
+    }
+
+    #[test]
+    fn test_regex_ignore_case() {
+        let mut filter = Filter {
+            use_regex: true,
+            ignore_case: true,
+            ..Filter::default()
+        };
+        filter.add_exclude(r"\.log$").unwrap();
+
+        assert!(!filter.matches("BUILD.LOG", Path::new("BUILD.LOG"), false));
+        assert!(filter.matches("build.rs", Path::new("build.rs"), false));
+        assert!(matches!(
+            filter.add_include("("),
+            Err(PatternError::Regex(_))
+        ));
+    }
+
+    #[test]
+    fn test_excludes_from_file() {
+        let mut filter = Filter::new();
+        let content = "# build output\ntarget\n\n  *.log  \n# editor files\n*.swp\n";
+        filter.add_excludes_from(content).unwrap();
+
+        assert_eq!(filter.exclude_patterns.len(), 3);
+        assert!(!filter.matches("target", Path::new("target"), true));
+        assert!(!filter.matches("debug.log", Path::new("debug.log"), false));
+        assert!(filter.matches("main.rs", Path::new("main.rs"), false));
+
+        let err = Filter::new().add_excludes_from("ok\n\n[broken\n");
+        assert!(matches!(err, Err((3, PatternError::Glob(_)))));
+    }
+
+    #[test]
+    fn test_brace_expansion() {
+        assert_eq!(expand_braces("*.{rs,toml}"), vec!["*.rs", "*.toml"]);
+        assert_eq!(
+            expand_braces("{src,tests}/*.{rs,md}"),
+            vec!["src/*.rs", "src/*.md", "tests/*.rs", "tests/*.md"]
+        );
+        assert_eq!(expand_braces("plain"), vec!["plain"]);
+        assert_eq!(expand_braces("{solo}"), vec!["{solo}"]);
+    }
+
+    #[test]
+    fn test_nested_and_escaped_braces() {
+        assert_eq!(expand_braces("a{b,c{d,e}}f"), vec!["abf", "acdf", "acef"]);
+        assert_eq!(expand_braces(r"\{a,b\}"), vec!["{a,b}"]);
+        assert_eq!(expand_braces(r"{a\,b,c}"), vec!["a,b", "c"]);
+
+        let mut filter = Filter::new();
+        filter.add_include("*.{rs,toml}").unwrap();
+        assert!(filter.matches("main.rs", Path::new("main.rs"), false));
+        assert!(filter.matches("Cargo.toml", Path::new("Cargo.toml"), false));
+        assert!(!filter.matches("README.md", Path::new("README.md"), false));
+    }
+
+    #[test]
+    fn test_full_path_matching() {
+        let mut filter = Filter::new();
+        filter.add_include("src/**/*.rs").unwrap();
+        let lib = Path::new("src/lib.rs");
+        let nested = Path::new("src/a/b/mod.rs");
+        let other = Path::new("tests/cli.rs");
+
+        // Basename matching is the default, so a pattern with `/` never matches
+        assert!(!filter.matches("lib.rs", lib, false));
+
+        filter.match_full_path = true;
+        assert!(filter.matches("lib.rs", lib, false));
+        assert!(filter.matches("mod.rs", nested, false));
+        assert!(!filter.matches("cli.rs", other, false));
+
+        // `*` stays within one component
+        let mut filter = Filter::new();
+        filter.match_full_path = true;
+        filter.add_include("src/*.rs").unwrap();
+        assert!(filter.matches("lib.rs", lib, false));
+        assert!(!filter.matches("mod.rs", nested, false));
     }
 }
@@ -1,5 +1,6 @@
-use std::time::SystemTime;
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
 /// Format file size in human-readable format
 pub fn format_size(size: u64, si: bool) -> String {
@@ -22,13 +23,96 @@ pub fn format_size(size: u64, si: bool) -> String {
         unit_idx += 1;
     }
 
-    if size_f >= 10.0 {
+    // Rounding can carry into the next unit: 1023.9K displays as 1.0M
+    if size_f.round() >= base && unit_idx < units.len() - 1 {
+        size_f /= base;
+        unit_idx += 1;
+    }
+
+    // Likewise 9.96K would print as "10.0K", so pick the precision after rounding
+    if (size_f * 10.0).round() >= 100.0 {
         format!("{:>3.0}{}", size_f, units[unit_idx])
     } else {
         format!("{:>3.1}{}", size_f, units[unit_idx])
     }
 }
 
+/// Parse a size such as `512`, `10K`, `1.5M` or `2GB` into bytes
+///
+/// Uses the same units as `format_size`: single-letter suffixes are powers
+/// of 1024, while `kB`/`MB`/... are SI powers of 1000.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", s))?;
+
+    let multiplier: f64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1.0,
+        "K" => 1024.0,
+        "M" => 1024.0_f64.powi(2),
+        "G" => 1024.0_f64.powi(3),
+        "T" => 1024.0_f64.powi(4),
+        "P" => 1024.0_f64.powi(5),
+        "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "TB" => 1e12,
+        "PB" => 1e15,
+        _ => return Err(format!("invalid size unit '{}'", unit)),
+    };
+
+    Ok((value * multiplier) as u64)
+}
+
+/// Parse a point in time from an ISO date (`2024-01-15`, `2024-01-15T08:30:00`,
+/// RFC 3339) or a duration before now such as `30m`, `24h`, `7d` or `2w`
+pub fn parse_time_bound(s: &str) -> Result<SystemTime, String> {
+    let s = s.trim();
+
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+        return Ok(datetime.into());
+    }
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S"))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        });
+    if let Some(naive) = naive {
+        return Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(SystemTime::from)
+            .ok_or_else(|| format!("invalid local time '{}'", s));
+    }
+
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid date or duration '{}'", s))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid duration unit '{}'", unit)),
+    };
+
+    SystemTime::now()
+        .checked_sub(Duration::from_secs(value.saturating_mul(seconds)))
+        .ok_or_else(|| format!("duration '{}' is too large", s))
+}
+
 /// Format timestamp for display
 pub fn format_time(time: SystemTime, format: Option<&str>) -> String {
     let datetime: DateTime<Local> = time.into();
@@ -36,6 +120,76 @@ pub fn format_time(time: SystemTime, format: Option<&str>) -> String {
     datetime.format(fmt).to_string()
 }
 
+/// Format timestamp for display in UTC, for `--utc`
+pub fn format_time_utc(time: SystemTime, format: Option<&str>) -> String {
+    let datetime: DateTime<Utc> = time.into();
+    let fmt = format.unwrap_or("%b %d %H:%M");
+    datetime.format(fmt).to_string()
+}
+
+/// Time format presets, like `ls --time-style`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeStyle {
+    Iso,
+    LongIso,
+    FullIso,
+    Relative,
+}
+
+impl TimeStyle {
+    /// The strftime format for this style, or `None` for `Relative`
+    pub fn strftime(self) -> Option<&'static str> {
+        match self {
+            TimeStyle::Iso => Some("%Y-%m-%d"),
+            TimeStyle::LongIso => Some("%Y-%m-%d %H:%M"),
+            TimeStyle::FullIso => Some("%Y-%m-%d %H:%M:%S%.9f %z"),
+            TimeStyle::Relative => None,
+        }
+    }
+}
+
+impl FromStr for TimeStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "iso" => Ok(TimeStyle::Iso),
+            "long-iso" => Ok(TimeStyle::LongIso),
+            "full-iso" => Ok(TimeStyle::FullIso),
+            "relative" => Ok(TimeStyle::Relative),
+            _ => Err(format!(
+                "unknown time style '{}' (expected iso, long-iso, full-iso or relative)",
+                s
+            )),
+        }
+    }
+}
+
+/// Format a timestamp relative to `now`, e.g. "3 days ago" or "in 2 hours"
+pub fn format_relative_time(time: SystemTime, now: SystemTime) -> String {
+    let (delta, future) = match now.duration_since(time) {
+        Ok(delta) => (delta, false),
+        Err(e) => (e.duration(), true),
+    };
+
+    let secs = delta.as_secs();
+    let (value, unit) = match secs {
+        0 => return "just now".to_string(),
+        1..=59 => (secs, "second"),
+        60..=3_599 => (secs / 60, "minute"),
+        3_600..=86_399 => (secs / 3_600, "hour"),
+        86_400..=31_535_999 => (secs / 86_400, "day"),
+        _ => (secs / 31_536_000, "year"),
+    };
+    let plural = if value == 1 { "" } else { "s" };
+
+    if future {
+        format!("in {} {}{}", value, unit, plural)
+    } else {
+        format!("{} {}{} ago", value, unit, plural)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -57,4 +211,112 @@ mod tests {
         assert_eq!(format_size(1000, true).trim(), "1.0kB");
         assert_eq!(format_size(1500, true).trim(), "1.5kB");
     }
+
+    #[test]
+    fn test_format_size_boundaries() {
+        let human = |size| format_size(size, false).trim().to_string();
+        let si = |size| format_size(size, true).trim().to_string();
+
+        assert_eq!(human(999), "999");
+        assert_eq!(human(1000), "1000");
+        assert_eq!(human(1023), "1023");
+        assert_eq!(human(1024), "1.0K");
+        assert_eq!(human(1500), "1.5K");
+        assert_eq!(human(10_188), "9.9K");
+        assert_eq!(human(10_200), "10K");
+        assert_eq!(human(1024 * 1024 - 1), "1.0M");
+
+        assert_eq!(si(999), "999");
+        assert_eq!(si(1000), "1.0kB");
+        assert_eq!(si(1023), "1.0kB");
+        assert_eq!(si(1024), "1.0kB");
+        assert_eq!(si(1500), "1.5kB");
+        assert_eq!(si(9_960), "10kB");
+        assert_eq!(si(999_999), "1.0MB");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("10M"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("1.5K"), Ok(1536));
+        assert_eq!(parse_size("2kB"), Ok(2000));
+        assert_eq!(parse_size("1G"), Ok(1 << 30));
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn test_parse_time_bound_duration() {
+        let bound = parse_time_bound("7d").unwrap();
+        let age = SystemTime::now().duration_since(bound).unwrap();
+        assert!(age >= Duration::from_secs(7 * 24 * 60 * 60));
+        assert!(age < Duration::from_secs(7 * 24 * 60 * 60 + 60));
+        assert!(parse_time_bound("24h").is_ok());
+        assert!(parse_time_bound("3y").is_err());
+    }
+
+    #[test]
+    fn test_time_style_presets() {
+        let time = parse_time_bound("2024-03-05T14:07:09").unwrap();
+        let format = |style: &str| {
+            let style: TimeStyle = style.parse().unwrap();
+            format_time(time, style.strftime())
+        };
+        assert_eq!(format("iso"), "2024-03-05");
+        assert_eq!(format("long-iso"), "2024-03-05 14:07");
+        assert!(format("full-iso").starts_with("2024-03-05 14:07:09.000000000 "));
+        assert_eq!("relative".parse(), Ok(TimeStyle::Relative));
+        assert!("posix".parse::<TimeStyle>().is_err());
+    }
+
+    #[test]
+    fn test_format_time_utc() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let fmt = Some("%Y-%m-%d %H:%M:%S %z");
+        assert_eq!(format_time_utc(time, fmt), "2023-11-14 22:13:20 +0000");
+        assert_eq!(format_time_utc(time, None), "Nov 14 22:13");
+
+        let local = DateTime::<Local>::from(time);
+        assert_eq!(
+            format_time(time, fmt),
+            local.format("%Y-%m-%d %H:%M:%S %z").to_string()
+        );
+        assert_eq!(
+            format_time(time, None),
+            local.format("%b %d %H:%M").to_string()
+        );
+    }
+
+    #[test]
+    fn test_format_relative_time() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let ago = |secs| format_relative_time(now - Duration::from_secs(secs), now);
+        assert_eq!(ago(0), "just now");
+        assert_eq!(ago(1), "1 second ago");
+        assert_eq!(ago(45), "45 seconds ago");
+        assert_eq!(ago(120), "2 minutes ago");
+        assert_eq!(ago(3 * 3600), "3 hours ago");
+        assert_eq!(ago(3 * 86_400), "3 days ago");
+        assert_eq!(ago(2 * 365 * 86_400), "2 years ago");
+        assert_eq!(
+            format_relative_time(now + Duration::from_secs(3600), now),
+            "in 1 hour"
+        );
+    }
+
+    #[test]
+    fn test_parse_time_bound_date() {
+        let bound = parse_time_bound("2024-01-15").unwrap();
+        assert_eq!(
+            format_time(bound, Some("%Y-%m-%d %H:%M")),
+            "2024-01-15 00:00"
+        );
+        let bound = parse_time_bound("2024-01-15T00:00:00Z").unwrap();
+        let secs = bound
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert_eq!(secs, 1_705_276_800);
+    }
 }
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Per-file git status, keyed by absolute path
+pub type GitStatus = HashMap<PathBuf, char>;
+
+/// Collect the git status of every changed file in the repository containing
+/// `root` by running `git status --porcelain`
+///
+/// Files are marked `M` (modified in the work tree), `A` (staged) or `?`
+/// (untracked); clean files are absent. Returns `None` if `root` isn't inside
+/// a git repository or git isn't available.
+pub fn status(root: &Path) -> Option<GitStatus> {
+    let toplevel = git(root, &["rev-parse", "--show-toplevel"])?;
+    let toplevel = PathBuf::from(toplevel.trim_end());
+
+    let output = git(
+        root,
+        &["status", "--porcelain", "-z", "--untracked-files=all"],
+    )?;
+    Some(parse_porcelain(&toplevel, &output))
+}
+
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+/// Parse `git status --porcelain -z` output, whose paths are relative to
+/// `toplevel`
+fn parse_porcelain(toplevel: &Path, output: &str) -> GitStatus {
+    let mut status = GitStatus::new();
+    let mut records = output.split('\0');
+
+    while let Some(record) = records.next() {
+        let (Some(code), Some(path)) = (record.get(..2), record.get(3..)) else {
+            continue;
+        };
+        let mut code = code.chars();
+        let (index, worktree) = (code.next().unwrap_or(' '), code.next().unwrap_or(' '));
+
+        // Renames and copies are followed by the original path
+        if matches!(index, 'R' | 'C') {
+            records.next();
+        }
+
+        let mark = if index == '?' {
+            '?'
+        } else if worktree != ' ' {
+            'M'
+        } else {
+            'A'
+        };
+        status.insert(toplevel.join(path), mark);
+    }
+
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn run_git(dir: &Path, args: &[&str]) {
+        let ok = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .unwrap()
+            .status
+            .success();
+        assert!(ok, "git {:?} failed", args);
+    }
+
+    #[test]
+    fn test_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("clean.txt"), "clean").unwrap();
+        fs::write(root.join("src/changed.rs"), "old").unwrap();
+
+        run_git(&root, &["init", "-q"]);
+        run_git(&root, &["add", "."]);
+        run_git(&root, &["commit", "-q", "-m", "init"]);
+
+        fs::write(root.join("src/changed.rs"), "new").unwrap();
+        fs::write(root.join("src/new.rs"), "").unwrap();
+        fs::write(root.join("staged.txt"), "").unwrap();
+        run_git(&root, &["add", "staged.txt"]);
+
+        let status = status(&root.join("src")).unwrap();
+        assert_eq!(status.len(), 3);
+        assert_eq!(status[&root.join("src/changed.rs")], 'M');
+        assert_eq!(status[&root.join("src/new.rs")], '?');
+        assert_eq!(status[&root.join("staged.txt")], 'A');
+        assert!(!status.contains_key(&root.join("clean.txt")));
+    }
+
+    #[test]
+    fn test_not_a_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(status(dir.path()).is_none());
+    }
+
+    #[test]
+    fn test_parse_rename() {
+        let status = parse_porcelain(Path::new("/repo"), "R  new.rs\0old.rs\0?? x\0");
+        assert_eq!(status.len(), 2);
+        assert_eq!(status[Path::new("/repo/new.rs")], 'A');
+        assert_eq!(status[Path::new("/repo/x")], '?');
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// A single pattern line from a `.gitignore` file
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    /// `!pattern` re-includes a previously ignored path
+    negated: bool,
+    /// `pattern/` only matches directories
+    dir_only: bool,
+    /// Patterns containing a slash match relative to the `.gitignore` directory,
+    /// all others match the file name at any depth
+    anchored: bool,
+}
+
+/// Rules loaded from one `.gitignore` file
+#[derive(Debug, Clone)]
+pub struct Gitignore {
+    root: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl Gitignore {
+    /// Load `dir/.gitignore`, if present
+    pub fn from_dir(dir: &Path) -> Option<Self> {
+        let content = fs::read_to_string(dir.join(".gitignore")).ok()?;
+        Some(Self::parse(dir, &content))
+    }
+
+    /// Parse `.gitignore` content whose patterns are relative to `root`
+    pub fn parse(root: &Path, content: &str) -> Self {
+        let rules = content.lines().filter_map(parse_rule).collect();
+        Self {
+            root: root.to_path_buf(),
+            rules,
+        }
+    }
+
+    /// Check a path against these rules.
+    ///
+    /// Returns `Some(true)` if ignored, `Some(false)` if re-included by a
+    /// negated pattern, and `None` if no rule applies.
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let relative = relative.to_string_lossy();
+        let name = path.file_name()?.to_string_lossy();
+
+        // The last matching rule wins
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| is_dir || !rule.dir_only)
+            .find(|rule| {
+                if rule.anchored {
+                    rule.pattern.matches_with(&relative, MATCH_OPTIONS)
+                } else {
+                    rule.pattern.matches_with(&name, MATCH_OPTIONS)
+                }
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    let mut line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    // A leading backslash escapes a literal `!` or `#`
+    let negated = line.starts_with('!');
+    if negated || line.starts_with("\\!") || line.starts_with("\\#") {
+        line = &line[1..];
+    }
+
+    let dir_only = line.ends_with('/');
+    let line = line.trim_end_matches('/');
+    let anchored = line.contains('/');
+    let line = line.trim_start_matches('/');
+    if line.is_empty() {
+        return None;
+    }
+
+    Some(Rule {
+        pattern: Pattern::new(line).ok()?,
+        negated,
+        dir_only,
+        anchored,
+    })
+}
+
+/// Check a path against a stack of `.gitignore` files, innermost last
+pub fn is_ignored(stack: &[Gitignore], path: &Path, is_dir: bool) -> bool {
+    stack
+        .iter()
+        .rev()
+        .find_map(|gitignore| gitignore.matched(path, is_dir))
+        .unwrap_or(false)
+}
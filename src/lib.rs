@@ -1,5 +1,28 @@
+/// Generate chainable setters on a config builder, one per listed field of
+/// the wrapped `config`
+macro_rules! builder_setters {
+    ($($field:ident: $ty:ty),* $(,)?) => {
+        $(
+            #[doc = concat!("Set `", stringify!($field), "`")]
+            pub fn $field(mut self, $field: $ty) -> Self {
+                self.config.$field = $field;
+                self
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "async")]
+pub mod async_tree;
+pub mod checksum;
+pub mod colors;
+pub mod config;
+pub mod exec;
 pub mod filter;
 pub mod format;
+pub mod git;
+pub mod gitignore;
+pub mod owner;
 pub mod printer;
 pub mod sort;
 pub mod tree;
@@ -1,20 +1,39 @@
-use std::io;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use std::path::PathBuf;
+use std::time::SystemTime;
 
 use clap::Parser;
-use tree_rust::filter::Filter;
-use tree_rust::printer::{print_tree, OutputFormat, PrintConfig};
-use tree_rust::sort::SortKey;
-use tree_rust::tree::{walk_directory, TreeConfig, TreeStats};
+use tree_rust::checksum::ChecksumAlgorithm;
+use tree_rust::colors::{use_color, LsColors};
+use tree_rust::config::Config;
+use tree_rust::exec::{exec_files, exec_targets, ExecTemplate};
+use tree_rust::filter::{Filter, FilterSpec, MatchMode, PatternMode, PermMatch};
+use tree_rust::format::{parse_size, parse_time_bound, TimeStyle};
+use tree_rust::git;
+use tree_rust::printer::{
+    print_trees, read_json_snapshot, read_text_tree, write_json_line, LineCharset, OutputFormat,
+    PrintConfig,
+};
+use tree_rust::sort::{GroupOrder, SortKey};
+use tree_rust::tree::{
+    collapse_chains, compute_dir_block_sizes, compute_dir_sizes, select_depth, tree_from_paths,
+    walk_directory, walk_streaming, Progress, TraversalOrder, TreeConfig, TreeStats,
+};
+
+/// Entries scanned between updates of the `--progress` count
+const PROGRESS_INTERVAL: usize = 1000;
 
 /// A Rust implementation of the Linux tree command
 #[derive(Parser, Debug)]
 #[command(name = "tree-rust")]
 #[command(author, version, about, long_about = None)]
+// `-h` is --human, as in tree(1), so clap's own `-h` help flag would collide
+#[command(disable_help_flag = true)]
 struct Args {
-    /// Directory to list (default: current directory)
+    /// Directories to list (default: current directory)
     #[arg(default_value = ".")]
-    directory: PathBuf,
+    directories: Vec<PathBuf>,
 
     // ===== Listing Options =====
     /// All files are listed (including hidden files)
@@ -29,15 +48,42 @@ struct Args {
     #[arg(short = 'l', long = "follow")]
     follow_symlinks: bool,
 
+    /// Stay on the current filesystem only
+    #[arg(short = 'x', long = "xdev")]
+    xdev: bool,
+
     /// Print the full path prefix for each file
     #[arg(short = 'f', long = "full-path")]
     full_path: bool,
 
+    /// Print full paths relative to this directory
+    #[arg(long = "relative-to", value_name = "DIR")]
+    relative_to: Option<PathBuf>,
+
+    /// Truncate names longer than N characters with an ellipsis
+    #[arg(long = "max-name-width", value_name = "N")]
+    max_name_width: Option<usize>,
+
     /// Descend only level directories deep
     #[arg(short = 'L', long = "level")]
     level: Option<usize>,
 
-    /// List only those files that match the pattern
+    /// Show only entries exactly N levels deep, under the directories leading
+    /// to them
+    #[arg(long = "at-depth", value_name = "N")]
+    at_depth: Option<usize>,
+
+    /// List --at-depth entries directly under the root instead
+    #[arg(long = "at-depth-flat", requires = "at_depth")]
+    at_depth_flat: bool,
+
+    /// Merge directories holding only one subdirectory into a single a/b/c
+    /// line
+    #[arg(long = "collapse")]
+    collapse: bool,
+
+    /// List only those files that match the pattern; a leading ! excludes
+    /// matches instead (\! for a literal !)
     #[arg(short = 'P', long = "pattern")]
     pattern: Option<Vec<String>>,
 
@@ -45,19 +91,93 @@ struct Args {
     #[arg(short = 'I', long = "ignore")]
     ignore: Option<Vec<String>>,
 
+    /// Read ignore patterns from a file, one per line
+    #[arg(long = "exclude-from", value_name = "FILE")]
+    exclude_from: Vec<PathBuf>,
+
+    /// List only files at least this large (e.g. 10K, 5M, 1G)
+    #[arg(long = "min-size", value_name = "SIZE", value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// List only files at most this large (e.g. 10K, 5M, 1G)
+    #[arg(long = "max-size", value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// List only files modified after DATE (ISO date or age like 7d, 24h)
+    #[arg(long = "newer-than", value_name = "DATE", value_parser = parse_time_bound)]
+    newer_than: Option<SystemTime>,
+
+    /// List only files modified before DATE (ISO date or age like 7d, 24h)
+    #[arg(long = "older-than", value_name = "DATE", value_parser = parse_time_bound)]
+    older_than: Option<SystemTime>,
+
+    /// List only files whose mode is exactly MODE (0777), has all of its bits
+    /// (-0002) or any of them (/0111)
+    #[arg(
+        long = "same-permissions",
+        value_name = "MODE",
+        allow_hyphen_values = true
+    )]
+    perm: Option<PermMatch>,
+
+    /// List directories matching -P in full
+    #[arg(long = "matchdirs")]
+    matchdirs: bool,
+
+    /// Apply -P patterns to files only, descending into every directory
+    #[arg(long = "pattern-files-only")]
+    pattern_files_only: bool,
+
     /// Ignore case when pattern matching
     #[arg(long = "ignore-case")]
     ignore_case: bool,
 
+    /// Filter by using .gitignore files
+    #[arg(long = "gitignore")]
+    gitignore: bool,
+
+    /// Don't exclude the patterns listed in the root's .treeignore file
+    #[arg(long = "no-treeignore")]
+    no_treeignore: bool,
+
+    /// Walk directories in parallel using N worker threads (0 = one per CPU)
+    #[arg(long = "threads", value_name = "N")]
+    threads: Option<usize>,
+
+    /// Do not print empty directories
+    #[arg(long = "prune")]
+    prune: bool,
+
     /// Omit the file/directory report at the end
     #[arg(long = "noreport")]
     noreport: bool,
 
+    /// Format the report line with {dirs}, {files}, {size} and {lines}
+    /// placeholders
+    #[arg(long = "report-format", value_name = "TEMPLATE")]
+    report_format: Option<String>,
+
     // ===== File Options =====
+    /// Print inode number of each file
+    #[arg(long = "inodes")]
+    inodes: bool,
+
+    /// Print a digest of each regular file: crc32 or sha256
+    #[arg(long = "checksum", value_name = "ALGORITHM")]
+    checksum: Option<ChecksumAlgorithm>,
+
     /// Print the protections for each file
     #[arg(short = 'p', long = "perm")]
     permissions: bool,
 
+    /// Displays file owner or UID number
+    #[arg(short = 'u', long = "owner")]
+    owner: bool,
+
+    /// Displays file group owner or GID number
+    #[arg(short = 'g', long = "group")]
+    group: bool,
+
     /// Print the size in bytes of each file
     #[arg(short = 's', long = "size")]
     size: bool,
@@ -70,6 +190,23 @@ struct Args {
     #[arg(long = "si")]
     si: bool,
 
+    /// For each directory report its size as the accumulation of its contents
+    #[arg(long = "du")]
+    du: bool,
+
+    /// Report allocated disk space instead of apparent file sizes
+    #[arg(long = "disk-usage")]
+    disk_usage: bool,
+
+    /// Show a running count of scanned entries on stderr while walking, if
+    /// it's a terminal
+    #[arg(long = "progress")]
+    progress: bool,
+
+    /// Count the lines in listed text files and report the total
+    #[arg(long = "wc")]
+    wc: bool,
+
     /// Print the date of last modification
     #[arg(short = 'D', long = "date")]
     date: bool,
@@ -78,6 +215,14 @@ struct Args {
     #[arg(long = "timefmt")]
     timefmt: Option<String>,
 
+    /// Time format preset: iso, long-iso, full-iso or relative
+    #[arg(long = "time-style", value_name = "STYLE")]
+    time_style: Option<TimeStyle>,
+
+    /// Show dates in UTC instead of the local time zone
+    #[arg(long = "utc")]
+    utc: bool,
+
     /// Append indicator (like ls -F)
     #[arg(short = 'F', long = "classify")]
     classify: bool,
@@ -99,15 +244,57 @@ struct Args {
     #[arg(long = "dirsfirst")]
     dirsfirst: bool,
 
-    /// Select sort: name, size, mtime, none
+    /// List files before directories
+    #[arg(long = "filesfirst", conflicts_with = "dirsfirst")]
+    filesfirst: bool,
+
+    /// Sort directories by the total size of their contents, largest first
+    #[arg(long = "size-sort-total")]
+    size_sort_total: bool,
+
+    /// Select sort: name, size, total-size, mtime, ctime, extension, random,
+    /// none
     #[arg(long = "sort")]
     sort: Option<String>,
 
+    /// Seed for --sort=random, to repeat the same order
+    #[arg(long = "seed", value_name = "N")]
+    seed: Option<u64>,
+
+    /// Sort names case-sensitively (uppercase before lowercase)
+    #[arg(long = "sort-case")]
+    sort_case: bool,
+
+    /// Sort names ignoring case, overriding a config file's sort-case setting
+    #[arg(long = "sort-ignore-case", conflicts_with = "sort_case")]
+    sort_ignore_case: bool,
+
+    /// Sort accented names next to their unaccented letters (é with e)
+    /// instead of after z
+    #[arg(long = "locale-sort")]
+    locale_sort: bool,
+
     // ===== Graphics Options =====
     /// Don't print indentation lines
     #[arg(short = 'i', long = "noindent")]
     noindent: bool,
 
+    /// Use charset for line drawing: ascii or utf-8
+    #[arg(long = "charset")]
+    charset: Option<String>,
+
+    /// Columns per nesting level in the tree lines [default: 4]
+    #[arg(long = "indent", value_name = "N")]
+    indent: Option<usize>,
+
+    /// Print non-printable characters in names as '?' (default on a terminal)
+    #[arg(short = 'q', long = "sanitize")]
+    sanitize: bool,
+
+    /// Print names as-is, even on a terminal
+    #[arg(short = 'N', long = "literal", conflicts_with = "sanitize")]
+    literal: bool,
+
     /// Turn colorization off always
     #[arg(short = 'n', long = "nocolor")]
     nocolor: bool,
@@ -121,17 +308,339 @@ struct Args {
     #[arg(short = 'J', long = "json")]
     json: bool,
 
+    /// Print JSON on a single line instead of indented
+    #[arg(long = "json-compact")]
+    json_compact: bool,
+
+    /// Print JSON as a bare array of roots, without the version wrapper
+    #[arg(long = "json-legacy")]
+    json_legacy: bool,
+
     /// Print out a TOON representation of the tree
     #[arg(short = 'T', long = "toon")]
     toon: bool,
+
+    /// Print out an XML representation of the tree
+    #[arg(short = 'X', long = "xml")]
+    xml: bool,
+
+    /// Print out a YAML representation of the tree
+    #[arg(long = "yaml")]
+    yaml: bool,
+
+    /// Print out a Graphviz DOT representation of the tree
+    #[arg(long = "dot")]
+    dot: bool,
+
+    /// Print out a Markdown nested list representation of the tree
+    #[arg(long = "markdown")]
+    markdown: bool,
+
+    /// Print out an HTML nested list with collapsible directories
+    #[arg(long = "html")]
+    html: bool,
+
+    /// Print one JSON object per entry per line (NDJSON), each as the walk
+    /// reaches it
+    #[arg(long = "ndjson")]
+    ndjson: bool,
+
+    /// Print a flat JSON array of entries linked by id and parent_id
+    #[arg(long = "json-flat")]
+    json_flat: bool,
+
+    /// Include each entry's depth in JSON and TOON output
+    #[arg(long = "show-depth")]
+    show_depth: bool,
+
+    /// Print each directory on one line followed by its entries
+    #[arg(long = "compact")]
+    compact: bool,
+
+    /// List --flat and --ndjson output level by level instead of depth-first;
+    /// no other format is affected
+    #[arg(long = "breadth-first")]
+    breadth_first: bool,
+
+    /// Print the full path of each entry, one per line, without tree lines
+    #[arg(long = "flat")]
+    flat: bool,
+
+    /// Run a command on each listed regular file after printing, with {}
+    /// replaced by its path; COMMAND is split on whitespace, without quoting
+    #[arg(
+        long = "exec",
+        value_name = "COMMAND",
+        conflicts_with_all = ["from_json", "parse_text"]
+    )]
+    exec: Option<ExecTemplate>,
+
+    /// Print the commands --exec would run to stdout instead of running them
+    #[arg(long = "exec-dry-run", requires = "exec")]
+    exec_dry_run: bool,
+
+    /// Treat -P and -I patterns as regular expressions instead of globs
+    #[arg(long = "regex")]
+    regex: bool,
+
+    /// How -P and -I patterns match names: glob, exact, prefix, suffix or
+    /// contains
+    #[arg(long = "match-mode", value_name = "MODE", conflicts_with = "regex")]
+    match_mode: Option<MatchMode>,
+
+    /// Whether entries must match any (default) or all -P patterns
+    #[arg(long = "pattern-mode", value_name = "MODE")]
+    pattern_mode: Option<PatternMode>,
+
+    /// Match -P and -I patterns against the path relative to the root
+    #[arg(long = "match-path")]
+    match_path: bool,
+
+    /// Follow symlinks given as arguments, but not ones found while walking
+    #[arg(long = "dereference-args")]
+    dereference_args: bool,
+
+    /// Skip .git, .svn, .hg, .bzr and CVS directories, even with -a
+    #[arg(long = "ignore-vcs")]
+    ignore_vcs: bool,
+
+    /// Drop directories with no files anywhere beneath them
+    #[arg(long = "only-dirs-with-files")]
+    only_dirs_with_files: bool,
+
+    /// Mark files with their git status: M modified, A staged, ? untracked
+    #[arg(long = "git-status")]
+    git_status: bool,
+
+    /// Show the number of entries in each directory after its name
+    #[arg(long = "count")]
+    count: bool,
+
+    /// Show each entry's share of its parent's total size as a bar
+    #[arg(long = "size-bar")]
+    size_bar: bool,
+
+    /// Print only the report line, not the tree
+    #[arg(long = "count-only")]
+    count_only: bool,
+
+    /// Print file counts per extension after the report
+    #[arg(long = "stats-by-ext")]
+    stats_by_ext: bool,
+
+    /// Report the deepest level reached and the average entries per directory
+    #[arg(long = "stats")]
+    stats: bool,
+
+    /// List at most N entries per directory
+    #[arg(long = "max-entries", value_name = "N")]
+    max_entries: Option<usize>,
+
+    /// Do not descend directories that contain more than N entries
+    #[arg(long = "filelimit", value_name = "N")]
+    filelimit: Option<usize>,
+
+    /// After the listing, show the N largest files across all trees with
+    /// their paths (text output only)
+    #[arg(long = "top", value_name = "N")]
+    top: Option<usize>,
+
+    /// Build the tree from paths read from stdin, one per line (e.g. from find)
+    #[arg(long = "from-stdin")]
+    from_stdin: bool,
+
+    /// Render a tree saved earlier with -J instead of walking directories
+    #[arg(long = "from-json", value_name = "FILE", conflicts_with = "from_stdin")]
+    from_json: Option<PathBuf>,
+
+    /// Render a tree saved earlier as plain text output instead of walking
+    /// directories
+    #[arg(
+        long = "parse-text",
+        value_name = "FILE",
+        conflicts_with_all = ["from_stdin", "from_json"]
+    )]
+    parse_text: Option<PathBuf>,
+
+    /// Load default settings from a TOML, JSON or YAML file; on/off settings
+    /// in it can be turned off with the flag's --no- form, e.g. --no-all
+    #[arg(long = "config", value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    no: Negations,
+
+    /// Output to file instead of stdout
+    #[arg(short = 'o', long = "output", value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Print help (long form only, since `-h` is --human)
+    #[arg(long = "help", action = clap::ArgAction::Help)]
+    help: Option<bool>,
+}
+
+/// Counterparts of the on/off flags, for turning off a setting from
+/// `--config`; whichever of a flag and its counterpart comes last wins
+#[derive(clap::Args, Debug)]
+struct Negations {
+    #[arg(long = "no-all", overrides_with = "all", hide = true)]
+    no_all: bool,
+    #[arg(long = "no-dirs-only", overrides_with = "dirs_only", hide = true)]
+    no_dirs_only: bool,
+    #[arg(long = "no-follow", overrides_with = "follow_symlinks", hide = true)]
+    no_follow: bool,
+    #[arg(long = "no-xdev", overrides_with = "xdev", hide = true)]
+    no_xdev: bool,
+    #[arg(long = "no-full-path", overrides_with = "full_path", hide = true)]
+    no_full_path: bool,
+    #[arg(long = "no-ignore-case", overrides_with = "ignore_case", hide = true)]
+    no_ignore_case: bool,
+    #[arg(long = "no-regex", overrides_with = "regex", hide = true)]
+    no_regex: bool,
+    #[arg(long = "no-match-path", overrides_with = "match_path", hide = true)]
+    no_match_path: bool,
+    #[arg(long = "no-matchdirs", overrides_with = "matchdirs", hide = true)]
+    no_matchdirs: bool,
+    #[arg(
+        long = "no-pattern-files-only",
+        overrides_with = "pattern_files_only",
+        hide = true
+    )]
+    no_pattern_files_only: bool,
+    #[arg(long = "no-gitignore", overrides_with = "gitignore", hide = true)]
+    no_gitignore: bool,
+    #[arg(long = "treeignore", overrides_with = "no_treeignore", hide = true)]
+    treeignore: bool,
+    #[arg(long = "no-ignore-vcs", overrides_with = "ignore_vcs", hide = true)]
+    no_ignore_vcs: bool,
+    #[arg(long = "no-prune", overrides_with = "prune", hide = true)]
+    no_prune: bool,
+    #[arg(
+        long = "no-only-dirs-with-files",
+        overrides_with = "only_dirs_with_files",
+        hide = true
+    )]
+    no_only_dirs_with_files: bool,
+    #[arg(
+        long = "no-dereference-args",
+        overrides_with = "dereference_args",
+        hide = true
+    )]
+    no_dereference_args: bool,
+    #[arg(long = "no-reverse", overrides_with = "reverse", hide = true)]
+    no_reverse: bool,
+    #[arg(long = "no-locale-sort", overrides_with = "locale_sort", hide = true)]
+    no_locale_sort: bool,
+    #[arg(long = "report", overrides_with = "noreport", hide = true)]
+    report: bool,
+    #[arg(long = "no-inodes", overrides_with = "inodes", hide = true)]
+    no_inodes: bool,
+    #[arg(long = "no-perm", overrides_with = "permissions", hide = true)]
+    no_perm: bool,
+    #[arg(long = "no-owner", overrides_with = "owner", hide = true)]
+    no_owner: bool,
+    #[arg(long = "no-group", overrides_with = "group", hide = true)]
+    no_group: bool,
+    #[arg(long = "no-size", overrides_with = "size", hide = true)]
+    no_size: bool,
+    #[arg(long = "no-human", overrides_with = "human", hide = true)]
+    no_human: bool,
+    #[arg(long = "no-si", overrides_with = "si", hide = true)]
+    no_si: bool,
+    #[arg(long = "no-disk-usage", overrides_with = "disk_usage", hide = true)]
+    no_disk_usage: bool,
+    #[arg(long = "no-wc", overrides_with = "wc", hide = true)]
+    no_wc: bool,
+    #[arg(long = "no-date", overrides_with = "date", hide = true)]
+    no_date: bool,
+    #[arg(long = "no-utc", overrides_with = "utc", hide = true)]
+    no_utc: bool,
+    #[arg(long = "no-classify", overrides_with = "classify", hide = true)]
+    no_classify: bool,
+    #[arg(long = "indent-lines", overrides_with = "noindent", hide = true)]
+    indent_lines: bool,
+    #[arg(long = "no-json-compact", overrides_with = "json_compact", hide = true)]
+    no_json_compact: bool,
+    #[arg(long = "no-json-legacy", overrides_with = "json_legacy", hide = true)]
+    no_json_legacy: bool,
+    #[arg(long = "no-show-depth", overrides_with = "show_depth", hide = true)]
+    no_show_depth: bool,
+    #[arg(long = "no-count", overrides_with = "count", hide = true)]
+    no_count: bool,
+    #[arg(long = "no-count-only", overrides_with = "count_only", hide = true)]
+    no_count_only: bool,
+    #[arg(long = "no-size-bar", overrides_with = "size_bar", hide = true)]
+    no_size_bar: bool,
+    #[arg(long = "no-stats-by-ext", overrides_with = "stats_by_ext", hide = true)]
+    no_stats_by_ext: bool,
+    #[arg(long = "no-stats", overrides_with = "stats", hide = true)]
+    no_stats: bool,
+}
+
+/// An on/off setting: set by its flag, cleared by the flag's counterpart,
+/// and otherwise as in the config file
+fn toggle(on: bool, off: bool, base: bool) -> bool {
+    (on || base) && !off
 }
 
 fn main() {
     let args = Args::parse();
 
-    // Build filter
-    let mut filter = Filter::new();
-    filter.ignore_case = args.ignore_case;
+    if args.level == Some(0) {
+        eprintln!("Invalid level, must be greater than 0.");
+        std::process::exit(1);
+    }
+
+    if args.at_depth == Some(0) {
+        eprintln!("Invalid depth, must be greater than 0.");
+        std::process::exit(1);
+    }
+
+    if args.indent == Some(0) {
+        eprintln!("Invalid indent, must be greater than 0.");
+        std::process::exit(1);
+    }
+
+    // Settings from --config are defaults; flags given on the command line
+    // take precedence
+    let config = match args.config {
+        Some(ref path) => match Config::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => Config::default(),
+    };
+    let (base, base_print) = (config.tree, config.print);
+
+    // Build filter. Patterns from --config were compiled with its match
+    // settings, so settle those first and compile the patterns again
+    let mut spec = FilterSpec::from(base.filter.clone());
+    spec.ignore_case = toggle(args.ignore_case, args.no.no_ignore_case, spec.ignore_case);
+    spec.use_regex = toggle(args.regex, args.no.no_regex, spec.use_regex);
+    spec.match_mode = args.match_mode.unwrap_or(spec.match_mode);
+    spec.pattern_mode = args.pattern_mode.unwrap_or(spec.pattern_mode);
+    spec.match_full_path = toggle(args.match_path, args.no.no_match_path, spec.match_full_path);
+    spec.match_dirs = toggle(args.matchdirs, args.no.no_matchdirs, spec.match_dirs);
+    spec.include_files_only = toggle(
+        args.pattern_files_only,
+        args.no.no_pattern_files_only,
+        spec.include_files_only,
+    );
+    let mut filter = match Filter::try_from(spec) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    filter.min_size = args.min_size.or(base.filter.min_size);
+    filter.max_size = args.max_size.or(base.filter.max_size);
+    filter.newer_than = args.newer_than.or(base.filter.newer_than);
+    filter.older_than = args.older_than.or(base.filter.older_than);
+    filter.perm = args.perm.or(base.filter.perm);
 
     if let Some(patterns) = &args.pattern {
         for p in patterns {
@@ -151,38 +660,127 @@ fn main() {
         }
     }
 
+    for path in &args.exclude_from {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        if let Err((line, e)) = filter.add_excludes_from(&content) {
+            eprintln!(
+                "Invalid ignore pattern at {}:{}: {}",
+                path.display(),
+                line,
+                e
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let group_order = if args.dirsfirst {
+        GroupOrder::DirsFirst
+    } else if args.filesfirst {
+        GroupOrder::FilesFirst
+    } else {
+        base.group_order
+    };
+
     // Determine sort key
     let sort_key = if args.unsorted {
         SortKey::None
     } else if args.sort_time {
         SortKey::Time
+    } else if args.size_sort_total {
+        SortKey::TotalSize
     } else if let Some(ref sort_str) = args.sort {
         SortKey::from_str(sort_str)
     } else {
-        SortKey::Name
+        base.sort_key
     };
 
+    // The progress line is redrawn in place, which only works on a terminal
+    let show_progress = args.progress && atty::is(atty::Stream::Stderr);
+
     // Build tree config
     let tree_config = TreeConfig {
-        show_hidden: args.all,
-        dirs_only: args.dirs_only,
-        max_depth: args.level,
-        follow_symlinks: args.follow_symlinks,
-        full_path: args.full_path,
+        show_hidden: toggle(args.all, args.no.no_all, base.show_hidden),
+        dirs_only: toggle(args.dirs_only, args.no.no_dirs_only, base.dirs_only),
+        // Nothing below --at-depth is shown, so don't walk it
+        max_depth: match (args.level.or(base.max_depth), args.at_depth) {
+            (Some(level), Some(depth)) => Some(level.min(depth)),
+            (level, depth) => level.or(depth),
+        },
+        follow_symlinks: toggle(
+            args.follow_symlinks,
+            args.no.no_follow,
+            base.follow_symlinks,
+        ),
+        full_path: toggle(args.full_path, args.no.no_full_path, base.full_path),
         filter,
         sort_key,
-        sort_reverse: args.reverse,
-        dirs_first: args.dirsfirst,
+        sort_reverse: toggle(args.reverse, args.no.no_reverse, base.sort_reverse),
+        sort_seed: args.seed.or(base.sort_seed),
+        case_sensitive_sort: args.sort_case || (base.case_sensitive_sort && !args.sort_ignore_case),
+        locale_sort: toggle(args.locale_sort, args.no.no_locale_sort, base.locale_sort),
+        group_order,
+        respect_gitignore: toggle(args.gitignore, args.no.no_gitignore, base.respect_gitignore),
+        respect_treeignore: toggle(
+            args.no.treeignore,
+            args.no_treeignore,
+            base.respect_treeignore,
+        ),
+        ignore_vcs: toggle(args.ignore_vcs, args.no.no_ignore_vcs, base.ignore_vcs),
+        threads: args.threads.or(base.threads),
+        prune_empty: toggle(args.prune, args.no.no_prune, base.prune_empty),
+        only_dirs_with_files: toggle(
+            args.only_dirs_with_files,
+            args.no.no_only_dirs_with_files,
+            base.only_dirs_with_files,
+        ),
+        dereference_args: toggle(
+            args.dereference_args,
+            args.no.no_dereference_args,
+            base.dereference_args,
+        ),
+        max_entries: args.max_entries.or(base.max_entries),
+        file_limit: args.filelimit.or(base.file_limit),
+        top_files: args.top.unwrap_or(base.top_files),
+        stay_on_filesystem: toggle(args.xdev, args.no.no_xdev, base.stay_on_filesystem),
+        custom_filter: None,
+        progress: show_progress.then(|| {
+            Progress::new(PROGRESS_INTERVAL, |scanned| {
+                eprint!("\r{} entries scanned", scanned)
+            })
+        }),
     };
 
-    // Determine colorization
-    let colorize = if args.nocolor {
-        false
-    } else if args.color {
-        true
+    // Determine colorization; see `use_color` for the precedence
+    let colorize = use_color(
+        args.color,
+        std::env::var_os("NO_COLOR").as_deref(),
+        args.nocolor || !base_print.colorize,
+        args.output.is_none() && atty::is(atty::Stream::Stdout),
+    );
+    // `colored` does its own env and tty checks; make it follow our decision
+    colored::control::set_override(colorize);
+
+    // Like GNU tree, names are only sanitized for a terminal unless asked
+    let sanitize_names = if args.sanitize || base_print.sanitize_names {
+        !args.literal
     } else {
-        // Auto-detect: colorize if stdout is a tty
-        atty::is(atty::Stream::Stdout)
+        !args.literal && args.output.is_none() && atty::is(atty::Stream::Stdout)
+    };
+
+    // Determine line drawing characters
+    let charset = match args.charset.as_deref().map(str::parse::<LineCharset>) {
+        Some(Ok(charset)) => charset,
+        Some(Err(e)) => {
+            eprintln!("Invalid charset: {}", e);
+            std::process::exit(1);
+        }
+        None => base_print.charset,
     };
 
     // Determine output format
@@ -190,36 +788,353 @@ fn main() {
         OutputFormat::Json
     } else if args.toon {
         OutputFormat::Toon
+    } else if args.xml {
+        OutputFormat::Xml
+    } else if args.yaml {
+        OutputFormat::Yaml
+    } else if args.dot {
+        OutputFormat::Dot
+    } else if args.flat {
+        OutputFormat::Flat
+    } else if args.markdown {
+        OutputFormat::Markdown
+    } else if args.ndjson {
+        OutputFormat::JsonLines
+    } else if args.json_flat {
+        OutputFormat::JsonFlat
+    } else if args.html {
+        OutputFormat::Html
+    } else if args.compact {
+        OutputFormat::Compact
     } else {
-        OutputFormat::Text
+        base_print.output_format
+    };
+    if args.breadth_first && !matches!(output_format, OutputFormat::Flat | OutputFormat::JsonLines)
+    {
+        eprintln!("Warning: --breadth-first only affects --flat and --ndjson output");
+    }
+    if args.top.is_some_and(|n| n > 0) && !matches!(output_format, OutputFormat::Text) {
+        eprintln!("Warning: --top only affects text output");
+    }
+
+    // Determine time format; an explicit --timefmt wins over --time-style
+    let (time_format, relative_time) = if args.timefmt.is_some() || args.time_style.is_some() {
+        let relative_time = args.timefmt.is_none() && args.time_style == Some(TimeStyle::Relative);
+        let time_format = args.timefmt.or_else(|| {
+            args.time_style
+                .and_then(TimeStyle::strftime)
+                .map(String::from)
+        });
+        (time_format, relative_time)
+    } else {
+        (base_print.time_format, base_print.relative_time)
     };
 
     // Build print config
-    let print_config = PrintConfig {
+    let mut print_config = PrintConfig {
         colorize,
-        show_permissions: args.permissions,
-        show_size: args.size || args.human || args.si,
-        human_readable: args.human || args.si,
-        si_units: args.si,
-        show_date: args.date,
-        time_format: args.timefmt,
-        show_type_indicator: args.classify,
-        no_indent: args.noindent,
-        full_path: args.full_path,
-        no_report: args.noreport,
+        show_inode: toggle(args.inodes, args.no.no_inodes, base_print.show_inode),
+        show_permissions: toggle(
+            args.permissions,
+            args.no.no_perm,
+            base_print.show_permissions,
+        ),
+        show_owner: toggle(args.owner, args.no.no_owner, base_print.show_owner),
+        show_group: toggle(args.group, args.no.no_group, base_print.show_group),
+        show_size: toggle(
+            args.size || args.human || args.si || args.du,
+            args.no.no_size,
+            base_print.show_size,
+        ),
+        human_readable: toggle(
+            args.human || args.si,
+            args.no.no_human,
+            base_print.human_readable,
+        ),
+        si_units: toggle(args.si, args.no.no_si, base_print.si_units),
+        show_date: toggle(args.date, args.no.no_date, base_print.show_date),
+        time_format,
+        relative_time,
+        use_utc: toggle(args.utc, args.no.no_utc, base_print.use_utc),
+        show_type_indicator: toggle(
+            args.classify,
+            args.no.no_classify,
+            base_print.show_type_indicator,
+        ),
+        no_indent: toggle(args.noindent, args.no.indent_lines, base_print.no_indent),
+        full_path: toggle(args.full_path, args.no.no_full_path, base_print.full_path),
+        no_report: toggle(args.noreport, args.no.report, base_print.no_report),
+        stats_by_ext: toggle(
+            args.stats_by_ext,
+            args.no.no_stats_by_ext,
+            base_print.stats_by_ext,
+        ),
+        show_depth_stats: toggle(args.stats, args.no.no_stats, base_print.show_depth_stats),
+        count_only: toggle(
+            args.count_only,
+            args.no.no_count_only,
+            base_print.count_only,
+        ),
+        show_child_count: toggle(args.count, args.no.no_count, base_print.show_child_count),
+        show_size_bar: toggle(args.size_bar, args.no.no_size_bar, base_print.show_size_bar),
+        git_status: None,
+        report_format: args.report_format.or(base_print.report_format),
         output_format,
+        traversal_order: if args.breadth_first {
+            TraversalOrder::BreadthFirst
+        } else {
+            base_print.traversal_order
+        },
+        ls_colors: LsColors::from_env(),
+        charset,
+        indent_width: args.indent.unwrap_or(base_print.indent_width),
+        sanitize_names,
+        use_block_size: toggle(
+            args.disk_usage,
+            args.no.no_disk_usage,
+            base_print.use_block_size,
+        ),
+        // Walked paths are canonical, so the base has to be too
+        relative_to: args
+            .relative_to
+            .map(|dir| dir.canonicalize().unwrap_or(dir))
+            .or(base_print.relative_to),
+        max_name_width: args.max_name_width.or(base_print.max_name_width),
+        show_lines: toggle(args.wc, args.no.no_wc, base_print.show_lines),
+        json_pretty: toggle(
+            args.no.no_json_compact,
+            args.json_compact,
+            base_print.json_pretty,
+        ),
+        json_legacy: toggle(
+            args.json_legacy,
+            args.no.no_json_legacy,
+            base_print.json_legacy,
+        ),
+        checksum: args.checksum.or(base_print.checksum),
+        show_depth: toggle(
+            args.show_depth,
+            args.no.no_show_depth,
+            base_print.show_depth,
+        ),
     };
 
-    // Walk the directory
-    let mut stats = TreeStats::default();
-    let path = args.directory.canonicalize().unwrap_or(args.directory);
-    let tree = walk_directory(&path, &tree_config, &mut stats, 0);
-
-    // Print the tree
+    // Open the output before walking so a bad path fails fast
     let stdout = io::stdout();
-    let mut handle = stdout.lock();
-    if let Err(e) = print_tree(&mut handle, &tree, &print_config, &stats) {
+    let mut writer: Box<dyn Write> = match &args.output {
+        Some(output) => match File::create(output) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("Unable to open output file '{}': {}", output.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(stdout.lock()),
+    };
+
+    // Walk each directory, reporting bad paths but carrying on with the rest
+    let mut roots = Vec::new();
+    let mut had_errors = false;
+
+    let dir_sizes = if print_config.use_block_size {
+        compute_dir_block_sizes
+    } else {
+        compute_dir_sizes
+    };
+
+    if args.from_stdin {
+        let paths: Vec<PathBuf> = io::stdin()
+            .lines()
+            .map_while(Result::ok)
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect();
+
+        let mut stats = TreeStats::default();
+        let (tree, errors) = tree_from_paths(&paths, &tree_config, &mut stats);
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+        had_errors = !errors.is_empty();
+        if let Some(mut tree) = tree {
+            if args.du || print_config.show_size_bar {
+                dir_sizes(&mut tree);
+            }
+            roots.push((tree, stats));
+        }
+    }
+
+    if let Some(ref path) = args.from_json {
+        let snapshot =
+            std::fs::File::open(path).and_then(|file| read_json_snapshot(io::BufReader::new(file)));
+        match snapshot {
+            Ok(trees) => {
+                for tree in trees {
+                    let stats = TreeStats::from_tree(&tree);
+                    roots.push((tree, stats));
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                had_errors = true;
+            }
+        }
+    }
+
+    if let Some(ref path) = args.parse_text {
+        let parsed =
+            std::fs::File::open(path).and_then(|file| read_text_tree(io::BufReader::new(file)));
+        match parsed {
+            Ok(trees) => {
+                for tree in trees {
+                    let stats = TreeStats::from_tree(&tree);
+                    roots.push((tree, stats));
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                had_errors = true;
+            }
+        }
+    }
+
+    let directories = if args.from_stdin || args.from_json.is_some() || args.parse_text.is_some() {
+        &[][..]
+    } else {
+        &args.directories[..]
+    };
+    // --ndjson lines are written as the walk finds each entry, unless
+    // something has to see the whole tree first
+    let stream_lines = matches!(print_config.output_format, OutputFormat::JsonLines)
+        && !print_config.count_only
+        && print_config.traversal_order == TraversalOrder::DepthFirst
+        && !tree_config.prune_empty
+        && !tree_config.only_dirs_with_files
+        && !matches!(tree_config.sort_key, SortKey::TotalSize)
+        && !(args.du || print_config.show_size_bar)
+        && args.at_depth.is_none()
+        && !args.collapse
+        && args.exec.is_none();
+    for directory in directories {
+        if let Err(e) = std::fs::metadata(directory) {
+            eprintln!("{}: {}", directory.display(), e);
+            had_errors = true;
+            continue;
+        }
+
+        // Keep a symlinked argument as-is so it's still shown as a link
+        let path = if args.dereference_args {
+            directory.clone()
+        } else {
+            directory
+                .canonicalize()
+                .unwrap_or_else(|_| directory.clone())
+        };
+        if stream_lines {
+            let mut result = Ok(());
+            walk_streaming(&path, &tree_config, |entry, depth| {
+                if result.is_ok() {
+                    result = write_json_line(&mut writer, entry, depth, &print_config);
+                }
+            });
+            if let Err(e) = result.and_then(|_| writer.flush()) {
+                eprintln!("Error writing output: {}", e);
+                std::process::exit(1);
+            }
+            continue;
+        }
+        let mut stats = TreeStats::default();
+        let mut tree = walk_directory(&path, &tree_config, &mut stats, 0);
+        if args.du || print_config.show_size_bar {
+            dir_sizes(&mut tree);
+        }
+        roots.push((tree, stats));
+    }
+
+    // Erase the progress line so it doesn't linger above the output
+    if show_progress {
+        eprint!("\r\x1b[K");
+    }
+
+    if let Some(depth) = args.at_depth {
+        for (tree, stats) in &mut roots {
+            select_depth(tree, depth, args.at_depth_flat);
+            *stats = TreeStats {
+                depth_limit: stats.depth_limit,
+                ..TreeStats::from_tree(tree)
+            };
+        }
+    }
+
+    // Merged chains still count as separate directories in the report
+    if args.collapse {
+        for (tree, _) in &mut roots {
+            collapse_chains(tree);
+        }
+    }
+
+    // The walk totals apparent sizes; --disk-usage reports allocated space
+    if print_config.use_block_size {
+        for (tree, stats) in &mut roots {
+            stats.total_size = tree
+                .iter()
+                .filter(|(depth, entry)| *depth > 0 && !entry.is_dir)
+                .map(|(_, entry)| entry.blocks_size())
+                .sum();
+        }
+    }
+
+    if print_config.show_lines {
+        for (tree, stats) in &mut roots {
+            stats.count_lines(tree);
+        }
+    }
+
+    // Look up git status once per root; roots outside a repository are
+    // simply left unannotated
+    if args.git_status {
+        for (tree, _) in &roots {
+            if let Some(status) = git::status(&tree.path) {
+                print_config
+                    .git_status
+                    .get_or_insert_with(Default::default)
+                    .extend(status);
+            }
+        }
+    }
+
+    // Print the trees
+    let roots: Vec<_> = roots.iter().map(|(tree, stats)| (tree, stats)).collect();
+    let result = print_trees(&mut writer, &roots, &print_config).and_then(|_| writer.flush());
+    if let Err(e) = result {
         eprintln!("Error writing output: {}", e);
         std::process::exit(1);
     }
+
+    // Commands run after the listing is out, so their output follows it
+    if let Some(ref template) = args.exec {
+        for (tree, _) in &roots {
+            if args.exec_dry_run {
+                // Even with -o, the commands are for the user, not the listing
+                let mut out = stdout.lock();
+                let result = exec_targets(tree)
+                    .try_for_each(|file| writeln!(out, "{}", template.render(&file.path)))
+                    .and_then(|_| out.flush());
+                if let Err(e) = result {
+                    eprintln!("Error writing output: {}", e);
+                    std::process::exit(1);
+                }
+            } else {
+                let (_, errors) = exec_files(tree, template);
+                for error in &errors {
+                    eprintln!("{}", error);
+                }
+                had_errors |= !errors.is_empty();
+            }
+        }
+    }
+
+    if had_errors {
+        std::process::exit(1);
+    }
 }
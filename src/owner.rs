@@ -0,0 +1,60 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    static USER_NAMES: RefCell<HashMap<u32, String>> = RefCell::new(HashMap::new());
+    static GROUP_NAMES: RefCell<HashMap<u32, String>> = RefCell::new(HashMap::new());
+}
+
+/// Resolve a uid to a user name, falling back to the numeric id
+///
+/// Lookups are cached for the lifetime of the thread.
+pub fn user_name(uid: u32) -> String {
+    USER_NAMES.with(|cache| {
+        cache
+            .borrow_mut()
+            .entry(uid)
+            .or_insert_with(|| {
+                uzers::get_user_by_uid(uid)
+                    .map(|user| user.name().to_string_lossy().to_string())
+                    .unwrap_or_else(|| uid.to_string())
+            })
+            .clone()
+    })
+}
+
+/// Resolve a gid to a group name, falling back to the numeric id
+///
+/// Lookups are cached for the lifetime of the thread.
+pub fn group_name(gid: u32) -> String {
+    GROUP_NAMES.with(|cache| {
+        cache
+            .borrow_mut()
+            .entry(gid)
+            .or_insert_with(|| {
+                uzers::get_group_by_gid(gid)
+                    .map(|group| group.name().to_string_lossy().to_string())
+                    .unwrap_or_else(|| gid.to_string())
+            })
+            .clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ids near u32::MAX are not assigned on any sane system
+    const UNKNOWN_ID: u32 = u32::MAX - 7;
+
+    #[test]
+    fn test_numeric_fallback() {
+        assert_eq!(user_name(UNKNOWN_ID), UNKNOWN_ID.to_string());
+        assert_eq!(group_name(UNKNOWN_ID), UNKNOWN_ID.to_string());
+    }
+
+    #[test]
+    fn test_root_resolves() {
+        assert_eq!(user_name(0), "root");
+    }
+}
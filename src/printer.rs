@@ -1,60 +1,276 @@
 use colored::*;
-use serde::Serialize;
-use std::io::{self, Write};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
 
-use crate::format::{format_size, format_time};
-use crate::tree::{TreeEntry, TreeStats};
+use crate::checksum::{file_checksum, ChecksumAlgorithm};
+use crate::colors::{paint_segment, LsColors};
+use crate::format::{format_relative_time, format_size, format_time, format_time_utc};
+use crate::git::GitStatus;
+use crate::owner::{group_name, user_name};
+use crate::tree::{LargestFiles, TraversalOrder, TreeEntry, TreeStats};
 
 /// Output format options
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum OutputFormat {
     #[default]
     Text,
     Json,
     Toon,
+    Xml,
+    Flat,
+    Markdown,
+    Yaml,
+    Dot,
+    /// One JSON object per entry per line (NDJSON)
+    JsonLines,
+    /// A flat JSON array of entries that refer to their parent by id
+    JsonFlat,
+    Html,
+    /// One line per directory listing its immediate entries
+    Compact,
 }
 
 /// Configuration for tree printing
-#[derive(Debug, Clone)]
+///
+/// Prefer building one with [`PrintConfigBuilder`], which keeps working as
+/// fields are added.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PrintConfig {
     pub colorize: bool,
+    pub show_inode: bool,
     pub show_permissions: bool,
+    pub show_owner: bool,
+    pub show_group: bool,
     pub show_size: bool,
     pub human_readable: bool,
     pub si_units: bool,
     pub show_date: bool,
     pub time_format: Option<String>,
+    /// Show dates relative to now ("3 days ago") instead of `time_format`
+    pub relative_time: bool,
+    /// Show dates in UTC rather than the local time zone
+    pub use_utc: bool,
     pub show_type_indicator: bool,
     pub no_indent: bool,
     pub full_path: bool,
     pub no_report: bool,
+    /// Custom report line with `{dirs}`, `{files}`, `{size}` and `{lines}`
+    /// placeholders
+    pub report_format: Option<String>,
+    /// Follow the report with file counts per extension
+    pub stats_by_ext: bool,
+    /// Follow the report with the deepest level reached and the average
+    /// number of entries per directory
+    pub show_depth_stats: bool,
+    /// Print only the report, without the tree itself
+    pub count_only: bool,
+    /// Follow directory names with their number of listed children, `[N]`
+    pub show_child_count: bool,
+    /// Show each entry's share of its parent directory's size as a bar
+    pub show_size_bar: bool,
+    /// Git status marks to show in front of each entry (`--git-status`);
+    /// `None` outside a repository
+    #[serde(skip)]
+    pub git_status: Option<GitStatus>,
     pub output_format: OutputFormat,
+    /// Entry order for the line-per-entry formats (`--flat`, `--ndjson`)
+    pub traversal_order: TraversalOrder,
+    /// Taken from `LS_COLORS` rather than config files
+    #[serde(skip)]
+    pub ls_colors: LsColors,
+    pub charset: LineCharset,
+    /// Columns per nesting level, including the branch glyph (default 4)
+    pub indent_width: usize,
+    /// Print control characters in names as `?` so they can't garble or
+    /// spoof terminal output
+    pub sanitize_names: bool,
+    /// Show allocated disk space rather than apparent sizes, like `du`
+    pub use_block_size: bool,
+    /// Print full paths relative to this directory when they're under it
+    pub relative_to: Option<PathBuf>,
+    /// Cut names in text output longer than this many characters short with
+    /// an ellipsis
+    pub max_name_width: Option<usize>,
+    /// Report the line total counted by `--wc`
+    pub show_lines: bool,
+    /// Indent JSON output; otherwise the whole document is one line
+    pub json_pretty: bool,
+    /// Print JSON as a bare array of roots, without the versioned wrapper
+    pub json_legacy: bool,
+    /// Digest to show for each regular file
+    pub checksum: Option<ChecksumAlgorithm>,
+    /// Include each entry's depth in JSON and TOON output
+    pub show_depth: bool,
 }
 
 impl Default for PrintConfig {
     fn default() -> Self {
         Self {
             colorize: true,
+            show_inode: false,
             show_permissions: false,
+            show_owner: false,
+            show_group: false,
             show_size: false,
             human_readable: false,
             si_units: false,
             show_date: false,
             time_format: None,
+            relative_time: false,
+            use_utc: false,
             show_type_indicator: false,
             no_indent: false,
             full_path: false,
             no_report: false,
+            report_format: None,
+            stats_by_ext: false,
+            show_depth_stats: false,
+            count_only: false,
+            show_child_count: false,
+            show_size_bar: false,
+            git_status: None,
             output_format: OutputFormat::Text,
+            traversal_order: TraversalOrder::DepthFirst,
+            ls_colors: LsColors::default(),
+            charset: LineCharset::Unicode,
+            indent_width: 4,
+            sanitize_names: false,
+            use_block_size: false,
+            relative_to: None,
+            max_name_width: None,
+            show_lines: false,
+            json_pretty: true,
+            json_legacy: false,
+            checksum: None,
+            show_depth: false,
         }
     }
 }
 
-// Tree drawing characters
-const BRANCH: &str = "├── ";
-const LAST_BRANCH: &str = "└── ";
-const VERTICAL: &str = "│   ";
-const EMPTY: &str = "    ";
+/// Chainable builder for [`PrintConfig`], starting from the defaults
+///
+/// ```
+/// use tree_rust::printer::{OutputFormat, PrintConfigBuilder};
+///
+/// let config = PrintConfigBuilder::new()
+///     .colorize(false)
+///     .show_size(true)
+///     .human_readable(true)
+///     .output_format(OutputFormat::Json)
+///     .build();
+/// assert!(config.show_size && config.human_readable);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PrintConfigBuilder {
+    config: PrintConfig,
+}
+
+impl PrintConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    builder_setters! {
+        colorize: bool,
+        show_inode: bool,
+        show_permissions: bool,
+        show_owner: bool,
+        show_group: bool,
+        show_size: bool,
+        human_readable: bool,
+        si_units: bool,
+        show_date: bool,
+        time_format: Option<String>,
+        relative_time: bool,
+        use_utc: bool,
+        show_type_indicator: bool,
+        no_indent: bool,
+        full_path: bool,
+        no_report: bool,
+        report_format: Option<String>,
+        stats_by_ext: bool,
+        show_depth_stats: bool,
+        count_only: bool,
+        show_child_count: bool,
+        show_size_bar: bool,
+        git_status: Option<GitStatus>,
+        output_format: OutputFormat,
+        traversal_order: TraversalOrder,
+        ls_colors: LsColors,
+        charset: LineCharset,
+        indent_width: usize,
+        sanitize_names: bool,
+        use_block_size: bool,
+        relative_to: Option<PathBuf>,
+        max_name_width: Option<usize>,
+        show_lines: bool,
+        json_pretty: bool,
+        json_legacy: bool,
+        checksum: Option<ChecksumAlgorithm>,
+        show_depth: bool,
+    }
+
+    pub fn build(self) -> PrintConfig {
+        self.config
+    }
+}
+
+/// Character set used to draw the tree lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineCharset {
+    #[default]
+    #[serde(alias = "utf-8", alias = "utf8")]
+    Unicode,
+    Ascii,
+}
+
+impl LineCharset {
+    /// Drawing strings as `(branch, last_branch, vertical, empty)`, each
+    /// `width` columns wide (at least 1)
+    fn lines(self, width: usize) -> (String, String, String, String) {
+        let (branch, last_branch, horizontal, vertical) = match self {
+            LineCharset::Unicode => ('├', '└', '─', '│'),
+            LineCharset::Ascii => ('|', '`', '-', '|'),
+        };
+        // A connector is its glyph, horizontal fill, then a space before the
+        // name; at width 1 only the glyph fits
+        let connector = |glyph: char| {
+            let mut line = glyph.to_string();
+            if width > 1 {
+                line.push_str(&horizontal.to_string().repeat(width - 2));
+                line.push(' ');
+            }
+            line
+        };
+        let pad = " ".repeat(width.saturating_sub(1));
+
+        (
+            connector(branch),
+            connector(last_branch),
+            format!("{}{}", vertical, pad),
+            " ".repeat(width),
+        )
+    }
+}
+
+impl FromStr for LineCharset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ascii" | "us-ascii" => Ok(LineCharset::Ascii),
+            "utf-8" | "utf8" | "unicode" => Ok(LineCharset::Unicode),
+            _ => Err(format!("unknown charset '{}' (expected ascii or utf-8)", s)),
+        }
+    }
+}
 
 /// Print the tree structure
 pub fn print_tree<W: Write>(
@@ -63,11 +279,80 @@ pub fn print_tree<W: Write>(
     config: &PrintConfig,
     stats: &TreeStats,
 ) -> io::Result<()> {
+    print_trees(writer, &[(entry, stats)], config)
+}
+
+/// Print several trees, one per root directory
+///
+/// Text output gets a report per tree plus a grand total when there is more
+/// than one root; structured formats wrap all roots in a single document.
+pub fn print_trees<W: Write>(
+    writer: &mut W,
+    roots: &[(&TreeEntry, &TreeStats)],
+    config: &PrintConfig,
+) -> io::Result<()> {
+    let entries: Vec<&TreeEntry> = roots.iter().map(|(entry, _)| *entry).collect();
+
+    if config.count_only {
+        return print_trees_text(writer, roots, config);
+    }
+
     match config.output_format {
-        OutputFormat::Text => print_tree_text(writer, entry, config, stats),
-        OutputFormat::Json => print_tree_json(writer, entry),
-        OutputFormat::Toon => print_tree_toon(writer, entry, config),
+        OutputFormat::Text => print_trees_text(writer, roots, config),
+        OutputFormat::Json => print_tree_json(writer, &entries, config),
+        OutputFormat::Toon => print_tree_toon(writer, roots, config),
+        OutputFormat::Xml => print_tree_xml(writer, &entries),
+        OutputFormat::Yaml => print_tree_yaml(writer, &entries, config),
+        OutputFormat::Dot => print_tree_dot(writer, &entries),
+        OutputFormat::Flat => entries
+            .iter()
+            .try_for_each(|entry| print_tree_flat(writer, entry, config)),
+        OutputFormat::Html => print_tree_html(writer, &entries),
+        OutputFormat::JsonLines => entries
+            .iter()
+            .try_for_each(|entry| print_tree_json_lines(writer, entry, config)),
+        OutputFormat::JsonFlat => print_tree_json_flat(writer, &entries, config),
+        OutputFormat::Markdown => entries
+            .iter()
+            .try_for_each(|entry| print_tree_markdown(writer, entry)),
+        OutputFormat::Compact => entries
+            .iter()
+            .try_for_each(|entry| print_tree_compact(writer, entry)),
+    }
+}
+
+fn print_trees_text<W: Write>(
+    writer: &mut W,
+    roots: &[(&TreeEntry, &TreeStats)],
+    config: &PrintConfig,
+) -> io::Result<()> {
+    let mut total = TreeStats::default();
+
+    for (idx, (entry, stats)) in roots.iter().enumerate() {
+        if idx > 0 && !config.count_only {
+            writeln!(writer)?;
+        }
+        print_tree_text(writer, entry, config, stats)?;
+        total.merge((*stats).clone());
+    }
+
+    if roots.len() > 1 && !config.no_report {
+        if !config.count_only {
+            writeln!(writer)?;
+        }
+        write!(writer, "Total: ")?;
+        print_report(writer, &total, config)?;
+    }
+
+    // One list across all roots, shown even without the report
+    if !total.largest_files.is_empty() {
+        if !config.count_only || !config.no_report {
+            writeln!(writer)?;
+        }
+        print_largest_files(writer, &total.largest_files, config)?;
     }
+
+    Ok(())
 }
 
 /// Print tree in text format
@@ -77,128 +362,551 @@ fn print_tree_text<W: Write>(
     config: &PrintConfig,
     stats: &TreeStats,
 ) -> io::Result<()> {
+    if config.count_only {
+        if config.no_report {
+            return Ok(());
+        }
+        return print_report(writer, stats, config);
+    }
+
     // Print root directory
     let root_name = format_entry_name(entry, config, true);
-    writeln!(writer, "{}", root_name)?;
+    write!(writer, "{}", root_name)?;
+    end_entry_line(writer, entry, "", config)?;
 
     // Print children
-    print_children(writer, entry, config, "")?;
+    let widths = ColumnWidths::measure(entry, config);
+    print_children(writer, entry, config, "", &widths)?;
 
     // Print statistics
     if !config.no_report {
         writeln!(writer)?;
-        let dir_word = if stats.directories == 1 {
-            "directory"
-        } else {
-            "directories"
-        };
-        let file_word = if stats.files == 1 { "file" } else { "files" };
+        print_report(writer, stats, config)?;
+    }
+
+    Ok(())
+}
+
+fn print_report<W: Write>(
+    writer: &mut W,
+    stats: &TreeStats,
+    config: &PrintConfig,
+) -> io::Result<()> {
+    let total_size = if config.human_readable {
+        format_size(stats.total_size, config.si_units)
+            .trim()
+            .to_string()
+    } else {
+        stats.total_size.to_string()
+    };
+
+    if let Some(ref template) = config.report_format {
+        writeln!(writer, "{}", render_report(template, stats, &total_size))?;
+    } else {
+        print_default_report(writer, stats, config, &total_size)?;
+    }
+
+    if config.stats_by_ext && !stats.extensions.is_empty() {
+        writeln!(writer, "{}", extension_breakdown(stats))?;
+    }
+
+    if config.show_depth_stats {
         writeln!(
             writer,
-            "{} {}, {} {}",
-            stats.directories, dir_word, stats.files, file_word
+            "max depth {}, {:.2} entries per directory",
+            stats.max_depth_reached,
+            stats.average_branching()
         )?;
     }
 
     Ok(())
 }
 
+fn print_largest_files<W: Write>(
+    writer: &mut W,
+    largest: &LargestFiles,
+    config: &PrintConfig,
+) -> io::Result<()> {
+    writeln!(writer, "Largest files:")?;
+    let files: Vec<_> = largest
+        .sorted()
+        .into_iter()
+        .map(|(size, path)| {
+            let size = if config.human_readable {
+                format_size(size, config.si_units).trim().to_string()
+            } else {
+                size.to_string()
+            };
+            (size, full_path(path, config))
+        })
+        .collect();
+    let width = files.iter().map(|(size, _)| size.len()).max().unwrap_or(0);
+    for (size, path) in files {
+        writeln!(writer, "  {:>width$}  {}", size, path, width = width)?;
+    }
+
+    Ok(())
+}
+
+fn print_default_report<W: Write>(
+    writer: &mut W,
+    stats: &TreeStats,
+    config: &PrintConfig,
+    total_size: &str,
+) -> io::Result<()> {
+    let dir_word = if stats.directories == 1 {
+        "directory"
+    } else {
+        "directories"
+    };
+    let file_word = if stats.files == 1 { "file" } else { "files" };
+    write!(
+        writer,
+        "{} {}, {} {}",
+        stats.directories, dir_word, stats.files, file_word
+    )?;
+    if config.show_size {
+        write!(writer, ", {} total", total_size)?;
+    }
+    if config.show_lines {
+        let line_word = if stats.total_lines == 1 {
+            "line"
+        } else {
+            "lines"
+        };
+        write!(writer, ", {} {}", stats.total_lines, line_word)?;
+    }
+    if stats.errors > 0 {
+        let error_word = if stats.errors == 1 {
+            "directory"
+        } else {
+            "directories"
+        };
+        write!(writer, ", {} {} unreadable", stats.errors, error_word)?;
+    }
+    writeln!(writer)
+}
+
+/// Number of extensions listed in the breakdown before the rest are dropped
+const MAX_EXTENSIONS_SHOWN: usize = 10;
+
+/// Summarize file counts per extension, most common first, e.g.
+/// `3 .rs, 2 .toml, 1 no-ext`
+fn extension_breakdown(stats: &TreeStats) -> String {
+    let mut counts: Vec<(&String, &usize)> = stats.extensions.iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+    counts
+        .iter()
+        .take(MAX_EXTENSIONS_SHOWN)
+        .map(|(ext, count)| {
+            if ext.is_empty() {
+                format!("{} no-ext", count)
+            } else {
+                format!("{} .{}", count, ext)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Substitute `{dirs}`, `{files}`, `{size}` and `{lines}` in a report template,
+/// leaving any other `{...}` text as-is
+fn render_report(template: &str, stats: &TreeStats, total_size: &str) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest.find('}').and_then(|end| {
+            let value = match &rest[1..end] {
+                "dirs" => stats.directories.to_string(),
+                "files" => stats.files.to_string(),
+                "size" => total_size.to_string(),
+                "lines" => stats.total_lines.to_string(),
+                _ => return None,
+            };
+            Some((value, end))
+        });
+
+        match value {
+            Some((value, end)) => {
+                out.push_str(&value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Widths of the metadata columns, measured over the whole tree so the
+/// columns, and the names after them, line up from row to row
+#[derive(Debug, Default)]
+struct ColumnWidths {
+    inode: usize,
+    owner: usize,
+    group: usize,
+    size: usize,
+    date: usize,
+}
+
+impl ColumnWidths {
+    fn measure(entry: &TreeEntry, config: &PrintConfig) -> Self {
+        // The historical fixed widths stay as minimums
+        let mut widths = ColumnWidths {
+            inode: 9,
+            owner: 8,
+            group: 8,
+            ..ColumnWidths::default()
+        };
+        for (_, child) in entry.iter().filter(|(depth, _)| *depth > 0) {
+            if config.show_inode {
+                widths.inode = widths.inode.max(inode_column(child).len());
+            }
+            if config.show_owner {
+                widths.owner = widths.owner.max(owner_column(child).chars().count());
+            }
+            if config.show_group {
+                widths.group = widths.group.max(group_column(child).chars().count());
+            }
+            if config.show_size {
+                widths.size = widths.size.max(size_column(child, config).len());
+            }
+            if config.show_date {
+                let date = date_column(child, config).unwrap_or_default();
+                widths.date = widths.date.max(date.chars().count());
+            }
+        }
+        widths
+    }
+}
+
+fn inode_column(entry: &TreeEntry) -> String {
+    entry
+        .inode()
+        .map_or_else(|| "?".to_string(), |i| i.to_string())
+}
+
+fn owner_column(entry: &TreeEntry) -> String {
+    entry
+        .uid()
+        .map(user_name)
+        .unwrap_or_else(|| "?".to_string())
+}
+
+fn group_column(entry: &TreeEntry) -> String {
+    entry
+        .gid()
+        .map(group_name)
+        .unwrap_or_else(|| "?".to_string())
+}
+
+fn size_column(entry: &TreeEntry, config: &PrintConfig) -> String {
+    if config.human_readable {
+        format_size(shown_size(entry, config), config.si_units)
+    } else {
+        shown_size(entry, config).to_string()
+    }
+}
+
+/// The size to show for an entry: its display size, or for entries without a
+/// computed aggregate its allocated size when `use_block_size` is set
+fn shown_size(entry: &TreeEntry, config: &PrintConfig) -> u64 {
+    if config.use_block_size && entry.aggregate_size.is_none() {
+        entry.blocks_size()
+    } else {
+        entry.display_size()
+    }
+}
+
+fn date_column(entry: &TreeEntry, config: &PrintConfig) -> Option<String> {
+    entry.modified().map(|time| format_entry_time(time, config))
+}
+
+/// Digest of a regular file, or `None` for directories, symlinks, special
+/// files and files that can't be read
+fn entry_checksum(entry: &TreeEntry, algorithm: ChecksumAlgorithm) -> Option<String> {
+    if !is_regular_file(entry) {
+        return None;
+    }
+    file_checksum(&entry.path, algorithm).ok()
+}
+
+/// The digest, `?` for an unreadable file, or blank for anything else
+fn checksum_column(entry: &TreeEntry, algorithm: ChecksumAlgorithm) -> String {
+    if !is_regular_file(entry) {
+        return String::new();
+    }
+    file_checksum(&entry.path, algorithm).unwrap_or_else(|_| "?".to_string())
+}
+
+fn is_regular_file(entry: &TreeEntry) -> bool {
+    !entry.is_symlink && entry.metadata.as_ref().is_some_and(|m| m.is_file())
+}
+
 fn print_children<W: Write>(
     writer: &mut W,
     entry: &TreeEntry,
     config: &PrintConfig,
     prefix: &str,
+    widths: &ColumnWidths,
 ) -> io::Result<()> {
     let children = &entry.children;
     let count = children.len();
+    let (branch_str, last_branch_str, vertical, empty) = config.charset.lines(config.indent_width);
 
     for (idx, child) in children.iter().enumerate() {
-        let is_last = idx == count - 1;
+        let is_last = idx == count - 1 && entry.omitted == 0;
 
         // Build the line prefix
         let (branch, child_prefix) = if config.no_indent {
             ("", "".to_string())
         } else if is_last {
-            (LAST_BRANCH, format!("{}{}", prefix, EMPTY))
+            (last_branch_str.as_str(), format!("{}{}", prefix, empty))
         } else {
-            (BRANCH, format!("{}{}", prefix, VERTICAL))
+            (branch_str.as_str(), format!("{}{}", prefix, vertical))
         };
 
         // Format the entry info
         let mut line = String::new();
 
         // Add metadata before the name if needed
+        if let Some(ref git_status) = config.git_status {
+            let mark = git_status.get(&child.path).copied().unwrap_or(' ');
+            let mark = match mark {
+                'M' => "M".red(),
+                'A' => "A".green(),
+                '?' => "?".yellow(),
+                _ => mark.to_string().normal(),
+            };
+            line.push_str(&paint_segment(mark, config.colorize));
+            line.push(' ');
+        }
+
+        if config.show_inode {
+            let inode = inode_column(child);
+            line.push_str(&format!("{:>width$} ", inode, width = widths.inode));
+        }
+
         if config.show_permissions {
-            line.push_str(&child.permissions_string());
+            let perms = child.permissions_string();
+            line.push_str(&paint_segment(perms.dimmed(), config.colorize));
             line.push(' ');
         }
 
+        if config.show_owner {
+            let owner = owner_column(child);
+            line.push_str(&format!("{:<width$} ", owner, width = widths.owner));
+        }
+
+        if config.show_group {
+            let group = group_column(child);
+            line.push_str(&format!("{:<width$} ", group, width = widths.group));
+        }
+
         if config.show_size {
-            let size_str = if config.human_readable {
-                format_size(child.size(), config.si_units)
-            } else {
-                format!("{:>10}", child.size())
-            };
-            line.push_str(&size_str);
+            let size_str = format!(
+                "{:>width$}",
+                size_column(child, config),
+                width = widths.size
+            );
+            line.push_str(&paint_segment(size_str.green(), config.colorize));
+            line.push(' ');
+        }
+
+        if config.show_size_bar {
+            line.push_str(&size_bar(
+                shown_size(child, config),
+                shown_size(entry, config),
+            ));
             line.push(' ');
         }
 
+        // Entries without a time get a blank column rather than shifting the
+        // name left
         if config.show_date {
-            if let Some(time) = child.modified() {
-                let time_str = format_time(time, config.time_format.as_deref());
-                line.push_str(&time_str);
-                line.push(' ');
-            }
+            let time_str = format!(
+                "{:<width$}",
+                date_column(child, config).unwrap_or_default(),
+                width = widths.date
+            );
+            line.push_str(&paint_segment(time_str.yellow(), config.colorize));
+            line.push(' ');
+        }
+
+        // Digests all have the same length, so no measuring pass is needed
+        if let Some(algorithm) = config.checksum {
+            let digest = format!(
+                "{:<width$}",
+                checksum_column(child, algorithm),
+                width = algorithm.hex_len()
+            );
+            line.push_str(&paint_segment(digest.dimmed(), config.colorize));
+            line.push(' ');
         }
 
         // Format name with color
         let name = format_entry_name(child, config, false);
 
-        // Print the line
-        if config.no_indent {
-            writeln!(writer, "{}{}", line, name)?;
-        } else {
-            writeln!(writer, "{}{}{}{}", prefix, branch, line, name)?;
-        }
-
-        // Handle errors
-        if let Some(ref error) = child.error {
-            let error_prefix = if config.no_indent {
-                ""
-            } else {
-                &child_prefix
-            };
-            writeln!(writer, "{}{}", error_prefix, error.red())?;
-        }
+        // Print the line; both prefixes are empty without indentation
+        write!(writer, "{}{}{}{}", prefix, branch, line, name)?;
+        end_entry_line(writer, child, &child_prefix, config)?;
 
         // Recursively print children
-        if !child.children.is_empty() {
-            print_children(writer, child, config, &child_prefix)?;
+        if !child.children.is_empty() || child.omitted > 0 {
+            print_children(writer, child, config, &child_prefix, widths)?;
         }
     }
 
+    if entry.omitted > 0 {
+        let more = format!("... {} more", entry.omitted);
+        let more = paint_segment(more.dimmed(), config.colorize);
+        let branch = if config.no_indent {
+            ""
+        } else {
+            last_branch_str.as_str()
+        };
+        writeln!(writer, "{}{}{}", prefix, branch, more)?;
+    }
+
     Ok(())
 }
 
+/// Finish an entry's line, adding its error or note if it has one
+///
+/// The message goes on a line of its own under `prefix`, except with
+/// `no_indent`, where such a line would read as another entry; there it
+/// trails the name instead.
+fn end_entry_line<W: Write>(
+    writer: &mut W,
+    entry: &TreeEntry,
+    prefix: &str,
+    config: &PrintConfig,
+) -> io::Result<()> {
+    let message = match (&entry.error, &entry.note) {
+        (Some(error), _) => paint_segment(error.red(), config.colorize),
+        (None, Some(note)) => paint_segment(format!("[{}]", note).dimmed(), config.colorize),
+        (None, None) => return writeln!(writer),
+    };
+    if config.no_indent {
+        writeln!(writer, " {}", message)
+    } else {
+        writeln!(writer, "\n{}{}", prefix, message)
+    }
+}
+
+/// Width of the `--size-bar` bar, excluding brackets
+const SIZE_BAR_WIDTH: usize = 10;
+
+/// Render `size` as a share of `total`, e.g. `[####      ]  40%`; an empty
+/// total gives an empty bar
+fn size_bar(size: u64, total: u64) -> String {
+    let fraction = if total == 0 {
+        0.0
+    } else {
+        (size as f64 / total as f64).min(1.0)
+    };
+    let filled = (fraction * SIZE_BAR_WIDTH as f64).round() as usize;
+    format!(
+        "[{}{}] {:>3}%",
+        "#".repeat(filled),
+        " ".repeat(SIZE_BAR_WIDTH - filled),
+        (fraction * 100.0).round() as u64
+    )
+}
+
+/// Shorten `name` to at most `width` characters, ending in `…` when cut
+fn truncate_name(name: String, width: usize) -> String {
+    if name.chars().count() <= width {
+        return name;
+    }
+    let mut truncated: String = name.chars().take(width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Replace control characters (tabs, newlines, escape sequences) with `?`,
+/// like GNU tree's `-q`
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_control() { '?' } else { c })
+        .collect()
+}
+
+fn format_entry_time(time: SystemTime, config: &PrintConfig) -> String {
+    if config.relative_time {
+        format_relative_time(time, SystemTime::now())
+    } else if config.use_utc {
+        format_time_utc(time, config.time_format.as_deref())
+    } else {
+        format_time(time, config.time_format.as_deref())
+    }
+}
+
+/// A path as `--full-path` prints it: relative to `relative_to` when it's
+/// inside that directory, otherwise as-is
+fn full_path(path: &Path, config: &PrintConfig) -> String {
+    let relative = config
+        .relative_to
+        .as_deref()
+        .and_then(|base| path.strip_prefix(base).ok());
+    match relative {
+        Some(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+        Some(relative) => relative.to_string_lossy().to_string(),
+        None => path.to_string_lossy().to_string(),
+    }
+}
+
 fn format_entry_name(entry: &TreeEntry, config: &PrintConfig, is_root: bool) -> String {
-    let name = if config.full_path && !is_root {
-        entry.path.to_string_lossy().to_string()
+    // Without tree lines nothing ties children to the root, so spell out its
+    // path too
+    let name = if config.full_path && (!is_root || config.no_indent) {
+        full_path(&entry.path, config)
     } else {
         entry.name.clone()
     };
+    let name = if config.sanitize_names {
+        sanitize(&name)
+    } else {
+        name
+    };
+    let name = match config.max_name_width {
+        Some(width) => truncate_name(name, width),
+        None => name,
+    };
 
-    let mut display_name = if config.colorize {
-        if entry.is_dir {
-            name.bold().blue().to_string()
+    let broken = entry.is_broken_symlink();
+    let mut display_name = if !config.colorize {
+        name
+    } else if let Some(painted) = config.ls_colors.paint(&name, entry) {
+        painted
+    } else {
+        let styled = if broken {
+            name.bold().red()
+        } else if entry.is_dir {
+            name.bold().blue()
         } else if entry.is_symlink {
-            name.cyan().to_string()
+            name.cyan()
+        } else if entry.is_fifo() {
+            name.yellow()
+        } else if entry.is_socket() {
+            name.bold().magenta()
+        } else if entry.is_block_device() || entry.is_char_device() {
+            name.bold().yellow()
         } else if entry.is_executable() {
-            name.bold().green().to_string()
+            name.bold().green()
         } else {
-            name
-        }
-    } else {
-        name
+            name.normal()
+        };
+        paint_segment(styled, true)
     };
 
     // Add type indicator
@@ -206,35 +914,102 @@ fn format_entry_name(entry: &TreeEntry, config: &PrintConfig, is_root: bool) ->
         display_name.push_str(entry.type_indicator());
     }
 
+    // Children left out by --max-entries still passed the filters; a
+    // directory that wasn't read has no count to show
+    let read = !entry.unread && entry.error.is_none() && entry.note.is_none();
+    if config.show_child_count && entry.is_dir && read {
+        let count = format!("[{}]", entry.children.len() + entry.omitted);
+        display_name.push(' ');
+        display_name.push_str(&paint_segment(count.dimmed(), config.colorize));
+    }
+
     // Add symlink target
     if entry.is_symlink {
         if let Some(ref target) = entry.symlink_target {
-            let target_str = target.to_string_lossy();
-            if config.colorize {
-                display_name = format!("{} -> {}", display_name, target_str.cyan());
-            } else {
-                display_name = format!("{} -> {}", display_name, target_str);
+            let mut target_str = target.to_string_lossy().to_string();
+            if config.sanitize_names {
+                target_str = sanitize(&target_str);
             }
+            let target_str = if broken {
+                target_str.red()
+            } else {
+                target_str.cyan()
+            };
+            display_name = format!(
+                "{} -> {}",
+                display_name,
+                paint_segment(target_str, config.colorize)
+            );
         }
     }
 
+    // Like GNU tree, flag links whose target is missing
+    if broken {
+        display_name.push_str(" [broken]");
+    }
+
     display_name
 }
 
+/// Version of the `-J` document layout, bumped whenever it changes
+/// incompatibly
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// The `-J` document: the schema version, then the tree under `root`, or
+/// under `roots` when several directories were listed
+#[derive(Serialize, Deserialize)]
+struct JsonDocument {
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    root: Option<TreeNode>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    roots: Vec<TreeNode>,
+}
+
+/// Either `-J` layout, for reading snapshots back
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonSnapshot {
+    Versioned(Box<JsonDocument>),
+    /// The bare array of roots written before versioning, or with
+    /// `--json-legacy`
+    Legacy(Vec<TreeNode>),
+}
+
 // JSON/TOML serialization structures
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct TreeNode {
     #[serde(rename = "type")]
     node_type: String,
     name: String,
+    /// Levels below the root; recomputed from the nesting when read back
+    #[serde(skip_serializing_if = "Option::is_none")]
+    depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inode: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    /// Modification time as seconds since the Unix epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<i64>,
+    /// Hex digest chosen with `--checksum`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     contents: Option<Vec<TreeNode>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     target: Option<String>,
+    /// Why a directory couldn't be read, e.g. `error opening dir: ...`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Why a directory's contents were left out otherwise, e.g.
+    /// `recursive, not followed`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
 }
 
-impl From<&TreeEntry> for TreeNode {
-    fn from(entry: &TreeEntry) -> Self {
+impl TreeNode {
+    fn new(entry: &TreeEntry, config: &PrintConfig) -> Self {
         let node_type = if entry.is_dir {
             "directory"
         } else if entry.is_symlink {
@@ -244,7 +1019,13 @@ impl From<&TreeEntry> for TreeNode {
         };
 
         let contents = if entry.is_dir && !entry.children.is_empty() {
-            Some(entry.children.iter().map(TreeNode::from).collect())
+            Some(
+                entry
+                    .children
+                    .iter()
+                    .map(|child| TreeNode::new(child, config))
+                    .collect(),
+            )
         } else {
             None
         };
@@ -257,30 +1038,600 @@ impl From<&TreeEntry> for TreeNode {
         TreeNode {
             node_type: node_type.to_string(),
             name: entry.name.clone(),
+            depth: config.show_depth.then_some(entry.depth),
+            inode: entry.inode().filter(|_| config.show_inode),
+            size: config.show_size.then(|| shown_size(entry, config)),
+            time: modified_epoch(entry).filter(|_| config.show_date),
+            checksum: config
+                .checksum
+                .and_then(|algorithm| entry_checksum(entry, algorithm)),
             contents,
             target,
+            error: entry.error.clone(),
+            note: entry.note.clone(),
         }
     }
 }
 
-fn print_tree_json<W: Write>(writer: &mut W, entry: &TreeEntry) -> io::Result<()> {
-    let tree_node = TreeNode::from(entry);
-    let json = serde_json::to_string_pretty(&[tree_node]).map_err(|e| {
-        io::Error::new(io::ErrorKind::Other, e)
-    })?;
-    writeln!(writer, "{}", json)?;
-    Ok(())
+impl TreeNode {
+    /// Rebuild an entry from a snapshot; there is no filesystem metadata, so
+    /// a saved size is kept as the entry's display size
+    fn into_entry(self, parent: &Path, depth: usize) -> TreeEntry {
+        let path = parent.join(&self.name);
+        let children = self
+            .contents
+            .unwrap_or_default()
+            .into_iter()
+            .map(|child| child.into_entry(&path, depth + 1))
+            .collect();
+
+        TreeEntry {
+            path,
+            name: self.name,
+            is_dir: self.node_type == "directory",
+            is_symlink: self.target.is_some(),
+            symlink_target: self.target.map(Into::into),
+            metadata: None,
+            children,
+            error: self.error,
+            note: self.note,
+            unread: false,
+            aggregate_size: self.size,
+            omitted: 0,
+            depth,
+        }
+    }
 }
 
-/// Print tree in TOON (Token-Oriented Object Notation) format
-/// TOON is optimized for LLMs with minimal token usage
-fn print_tree_toon<W: Write>(writer: &mut W, entry: &TreeEntry, config: &PrintConfig) -> io::Result<()> {
-    writeln!(writer, "# TOON - Tree Output")?;
-    print_toon_entry(writer, entry, 0, config)?;
-    Ok(())
+/// Read trees saved with `-J` back into entries, one per root
+///
+/// Snapshots carry no filesystem metadata, so columns such as permissions,
+/// owners and dates render as unknown or are left out.
+pub fn read_json_snapshot<R: Read>(reader: R) -> io::Result<Vec<TreeEntry>> {
+    let snapshot: JsonSnapshot = serde_json::from_reader(reader).map_err(io::Error::other)?;
+    let nodes = match snapshot {
+        JsonSnapshot::Versioned(document) if document.version > JSON_SCHEMA_VERSION => {
+            return Err(io::Error::other(format!(
+                "unsupported snapshot version {} (newest known is {})",
+                document.version, JSON_SCHEMA_VERSION
+            )));
+        }
+        JsonSnapshot::Versioned(document) => {
+            let JsonDocument { root, roots, .. } = *document;
+            root.into_iter().chain(roots).collect()
+        }
+        JsonSnapshot::Legacy(nodes) => nodes,
+    };
+    Ok(nodes
+        .into_iter()
+        .map(|node| node.into_entry(Path::new(""), 0))
+        .collect())
 }
 
-fn print_toon_entry<W: Write>(writer: &mut W, entry: &TreeEntry, depth: usize, config: &PrintConfig) -> io::Result<()> {
+/// Read trees saved as plain text output back into entries, one per root
+///
+/// The inverse of the text format in either charset: an entry's depth comes
+/// from the column of its `├──`/`└──` (or `|--`/`` `-- ``) connector, entries
+/// with children or a trailing `/` become directories, `name -> target`
+/// becomes a symlink, and report and error lines are skipped. Metadata
+/// columns aren't recognized and stay part of the name.
+pub fn read_text_tree<R: BufRead>(reader: R) -> io::Result<Vec<TreeEntry>> {
+    let ansi = Regex::new(r"\x1b\[[0-9;]*m").unwrap();
+    let report = Regex::new(r"^(Total: )?\d+ director(y|ies), \d+ files?").unwrap();
+
+    let mut roots = Vec::new();
+    // The path from the current root to the last entry read, with depths
+    let mut open: Vec<(usize, TreeEntry)> = Vec::new();
+    let mut expect_root = true;
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = ansi.replace_all(&line, "");
+        if line.trim().is_empty() {
+            expect_root = true;
+            continue;
+        }
+
+        match parse_text_line(&line) {
+            Some((depth, text)) if !open.is_empty() => {
+                close_entries(&mut open, depth);
+                let parent = &mut open.last_mut().unwrap().1;
+                let omitted = text
+                    .strip_prefix("... ")
+                    .and_then(|rest| rest.strip_suffix(" more"))
+                    .and_then(|count| count.parse().ok());
+                if let Some(omitted) = omitted {
+                    parent.omitted = omitted;
+                } else {
+                    let mut entry = text_entry(&parent.path, text);
+                    entry.depth = parent.depth + 1;
+                    open.push((depth, entry));
+                }
+            }
+            None if expect_root && !report.is_match(&line) => {
+                close_entries(&mut open, 1);
+                roots.extend(open.pop().map(|(_, root)| root));
+                let mut root = text_entry(Path::new(""), &line);
+                root.is_dir = true;
+                open.push((0, root));
+            }
+            _ => {}
+        }
+        expect_root = false;
+    }
+
+    close_entries(&mut open, 1);
+    roots.extend(open.pop().map(|(_, root)| root));
+    Ok(roots)
+}
+
+/// Split a text output line into its depth and the text after the connector,
+/// or `None` if it isn't an entry line
+fn parse_text_line(line: &str) -> Option<(usize, &str)> {
+    let mut column = 0;
+    let mut chars = line.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        let connector = match c {
+            '├' | '└' | '`' => true,
+            '|' => matches!(chars.peek(), Some((_, '-'))),
+            '│' | ' ' => false,
+            _ => return None,
+        };
+        if !connector {
+            column += 1;
+            continue;
+        }
+
+        // The connector's width, glyph to name, is the indent width
+        let mut width = 1;
+        while let Some((_, '─' | '-')) = chars.peek() {
+            chars.next();
+            width += 1;
+        }
+        if let Some((_, ' ')) = chars.peek() {
+            chars.next();
+            width += 1;
+        }
+        if column % width != 0 {
+            return None;
+        }
+        let rest = chars.peek().map_or("", |&(idx, _)| &line[idx..]);
+        return Some((column / width + 1, rest));
+    }
+    None
+}
+
+/// Attach every open entry at `depth` or deeper to its parent
+fn close_entries(open: &mut Vec<(usize, TreeEntry)>, depth: usize) {
+    while open.len() > 1 && open.last().is_some_and(|(d, _)| *d >= depth) {
+        let (_, child) = open.pop().unwrap();
+        let parent = &mut open.last_mut().unwrap().1;
+        parent.is_dir = true;
+        parent.children.push(child);
+    }
+}
+
+/// Build an entry from the name part of a text line
+fn text_entry(parent: &Path, text: &str) -> TreeEntry {
+    let text = text.strip_suffix(" [broken]").unwrap_or(text);
+    let (name, target) = match text.split_once(" -> ") {
+        Some((name, target)) => (name, Some(target)),
+        None => (text, None),
+    };
+    let is_dir = name.len() > 1 && name.ends_with('/');
+    let name = if is_dir {
+        &name[..name.len() - 1]
+    } else {
+        name
+    };
+
+    TreeEntry {
+        path: parent.join(name),
+        name: name.to_string(),
+        is_dir,
+        is_symlink: target.is_some(),
+        symlink_target: target.map(Into::into),
+        metadata: None,
+        children: Vec::new(),
+        error: None,
+        note: None,
+        unread: false,
+        aggregate_size: None,
+        omitted: 0,
+        depth: 0,
+    }
+}
+
+fn modified_epoch(entry: &TreeEntry) -> Option<i64> {
+    let modified = entry.modified()?;
+    let secs = match modified.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(after) => after.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    };
+    Some(secs)
+}
+
+fn print_tree_json<W: Write>(
+    writer: &mut W,
+    entries: &[&TreeEntry],
+    config: &PrintConfig,
+) -> io::Result<()> {
+    let mut tree_nodes: Vec<TreeNode> = entries
+        .iter()
+        .map(|entry| TreeNode::new(entry, config))
+        .collect();
+    let json = if config.json_legacy {
+        to_json(&tree_nodes, config.json_pretty)
+    } else {
+        let root = (tree_nodes.len() == 1).then(|| tree_nodes.remove(0));
+        let document = JsonDocument {
+            version: JSON_SCHEMA_VERSION,
+            root,
+            roots: tree_nodes,
+        };
+        to_json(&document, config.json_pretty)
+    }
+    .map_err(io::Error::other)?;
+    writeln!(writer, "{}", json)?;
+    Ok(())
+}
+
+fn to_json<T: Serialize>(value: &T, pretty: bool) -> serde_json::Result<String> {
+    if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    }
+}
+
+fn print_tree_yaml<W: Write>(
+    writer: &mut W,
+    entries: &[&TreeEntry],
+    config: &PrintConfig,
+) -> io::Result<()> {
+    let tree_nodes: Vec<TreeNode> = entries
+        .iter()
+        .map(|entry| TreeNode::new(entry, config))
+        .collect();
+    let yaml = serde_yaml::to_string(&tree_nodes).map_err(io::Error::other)?;
+    write!(writer, "{}", yaml)?;
+    Ok(())
+}
+
+fn print_tree_xml<W: Write>(writer: &mut W, entries: &[&TreeEntry]) -> io::Result<()> {
+    writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(writer, "<tree>")?;
+    for entry in entries {
+        print_xml_entry(writer, entry, 1)?;
+    }
+    writeln!(writer, "</tree>")?;
+    Ok(())
+}
+
+fn print_xml_entry<W: Write>(writer: &mut W, entry: &TreeEntry, depth: usize) -> io::Result<()> {
+    let indent = "  ".repeat(depth);
+    let tag = if entry.is_dir {
+        "directory"
+    } else if entry.is_symlink {
+        "link"
+    } else {
+        "file"
+    };
+
+    write!(
+        writer,
+        "{}<{} name=\"{}\"",
+        indent,
+        tag,
+        xml_escape(&entry.name)
+    )?;
+    if let Some(ref target) = entry.symlink_target {
+        write!(
+            writer,
+            " target=\"{}\"",
+            xml_escape(&target.to_string_lossy())
+        )?;
+    }
+
+    if entry.children.is_empty() {
+        writeln!(writer, "/>")?;
+        return Ok(());
+    }
+
+    writeln!(writer, ">")?;
+    for child in &entry.children {
+        print_xml_entry(writer, child, depth + 1)?;
+    }
+    writeln!(writer, "{}</{}>", indent, tag)?;
+    Ok(())
+}
+
+/// Print tree as an HTML fragment of nested lists, with each directory in a
+/// collapsible `<details>` element
+///
+/// Items carry `tree-dir` or `tree-file` classes for styling.
+fn print_tree_html<W: Write>(writer: &mut W, entries: &[&TreeEntry]) -> io::Result<()> {
+    writeln!(writer, "<ul class=\"tree\">")?;
+    for entry in entries {
+        print_html_entry(writer, entry, 1)?;
+    }
+    writeln!(writer, "</ul>")?;
+    Ok(())
+}
+
+fn print_html_entry<W: Write>(writer: &mut W, entry: &TreeEntry, depth: usize) -> io::Result<()> {
+    let indent = "  ".repeat(depth);
+    let mut label = xml_escape(&entry.name);
+    if let Some(ref target) = entry.symlink_target {
+        label.push_str(" -&gt; ");
+        label.push_str(&xml_escape(&target.to_string_lossy()));
+    }
+
+    if !entry.is_dir {
+        return writeln!(writer, "{}<li class=\"tree-file\">{}</li>", indent, label);
+    }
+
+    write!(
+        writer,
+        "{}<li class=\"tree-dir\"><details open><summary>{}</summary>",
+        indent, label
+    )?;
+    if entry.children.is_empty() {
+        return writeln!(writer, "</details></li>");
+    }
+
+    writeln!(writer)?;
+    writeln!(writer, "{}  <ul>", indent)?;
+    for child in &entry.children {
+        print_html_entry(writer, child, depth + 2)?;
+    }
+    writeln!(writer, "{}  </ul>", indent)?;
+    writeln!(writer, "{}</details></li>", indent)
+}
+
+/// Escape the characters that are not allowed in XML attribute values
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Print tree as a Graphviz `digraph`, one node per entry
+fn print_tree_dot<W: Write>(writer: &mut W, entries: &[&TreeEntry]) -> io::Result<()> {
+    writeln!(writer, "digraph tree {{")?;
+    writeln!(writer, "  rankdir=LR;")?;
+    let mut next_id = 0;
+    for entry in entries {
+        print_dot_entry(writer, entry, None, &mut next_id)?;
+    }
+    writeln!(writer, "}}")?;
+    Ok(())
+}
+
+fn print_dot_entry<W: Write>(
+    writer: &mut W,
+    entry: &TreeEntry,
+    parent: Option<usize>,
+    next_id: &mut usize,
+) -> io::Result<()> {
+    let id = *next_id;
+    *next_id += 1;
+
+    let shape = if entry.is_dir {
+        "folder"
+    } else if entry.is_symlink {
+        "cds"
+    } else {
+        "note"
+    };
+    writeln!(
+        writer,
+        "  n{} [label=\"{}\", shape={}];",
+        id,
+        dot_escape(&entry.name),
+        shape
+    )?;
+    if let Some(parent) = parent {
+        writeln!(writer, "  n{} -> n{};", parent, id)?;
+    }
+
+    for child in &entry.children {
+        print_dot_entry(writer, child, Some(id), next_id)?;
+    }
+    Ok(())
+}
+
+/// Escape a string for use inside a double-quoted DOT identifier
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Print the full path of every entry, one per line, without tree graphics
+fn print_tree_flat<W: Write>(
+    writer: &mut W,
+    entry: &TreeEntry,
+    config: &PrintConfig,
+) -> io::Result<()> {
+    for (_, node) in entry.iter_in(config.traversal_order) {
+        writeln!(writer, "{}", node.path.display())?;
+    }
+    Ok(())
+}
+
+/// Print each directory on one line followed by its immediate entries, e.g.
+/// `src/: lib.rs, main.rs`; subdirectories get lines of their own
+fn print_tree_compact<W: Write>(writer: &mut W, entry: &TreeEntry) -> io::Result<()> {
+    for (_, dir) in entry.iter().filter(|(_, node)| node.is_dir) {
+        let mut names: Vec<String> = dir.children.iter().map(|c| c.name.clone()).collect();
+        if dir.omitted > 0 {
+            names.push(format!("... {} more", dir.omitted));
+        }
+        let path = dir.path.to_string_lossy();
+        let separator = if path.ends_with('/') { "" } else { "/" };
+        if names.is_empty() {
+            writeln!(writer, "{}{}:", path, separator)?;
+        } else {
+            writeln!(writer, "{}{}: {}", path, separator, names.join(", "))?;
+        }
+    }
+    Ok(())
+}
+
+/// One line of JSON Lines output: a single entry without its children
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    depth: usize,
+    path: String,
+    #[serde(rename = "type")]
+    node_type: &'static str,
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<&'a str>,
+}
+
+/// Print one JSON object per entry, each on its own line, so output can be
+/// consumed incrementally (e.g. by `jq`) without holding a whole document
+fn print_tree_json_lines<W: Write>(
+    writer: &mut W,
+    entry: &TreeEntry,
+    config: &PrintConfig,
+) -> io::Result<()> {
+    for (depth, node) in entry.iter_in(config.traversal_order) {
+        write_json_line(writer, node, depth, config)?;
+    }
+    Ok(())
+}
+
+/// Write the `--ndjson` line for one entry `depth` levels below its root,
+/// e.g. as [`walk_streaming`](crate::tree::walk_streaming) hands it over
+pub fn write_json_line<W: Write>(
+    writer: &mut W,
+    node: &TreeEntry,
+    depth: usize,
+    config: &PrintConfig,
+) -> io::Result<()> {
+    let line = JsonLine {
+        depth,
+        path: node.path.to_string_lossy().to_string(),
+        node_type: if node.is_dir {
+            "directory"
+        } else if node.is_symlink {
+            "link"
+        } else {
+            "file"
+        },
+        name: &node.name,
+        size: config.show_size.then(|| shown_size(node, config)),
+        time: modified_epoch(node).filter(|_| config.show_date),
+        target: node
+            .symlink_target
+            .as_ref()
+            .map(|p| p.to_string_lossy().to_string()),
+        error: node.error.as_deref(),
+        note: node.note.as_deref(),
+    };
+    serde_json::to_writer(&mut *writer, &line).map_err(io::Error::other)?;
+    writeln!(writer)
+}
+
+/// One entry of `--json-flat` output; the tree is rebuilt by following
+/// `parent_id`, which is `null` for roots
+#[derive(Serialize)]
+struct FlatNode<'a> {
+    id: usize,
+    parent_id: Option<usize>,
+    #[serde(rename = "type")]
+    node_type: &'static str,
+    name: &'a str,
+    path: String,
+}
+
+/// Print every entry of every root in one JSON array, numbered depth-first
+/// from 0
+fn print_tree_json_flat<W: Write>(
+    writer: &mut W,
+    entries: &[&TreeEntry],
+    config: &PrintConfig,
+) -> io::Result<()> {
+    fn collect<'a>(entry: &'a TreeEntry, parent_id: Option<usize>, nodes: &mut Vec<FlatNode<'a>>) {
+        let id = nodes.len();
+        nodes.push(FlatNode {
+            id,
+            parent_id,
+            node_type: if entry.is_dir {
+                "directory"
+            } else if entry.is_symlink {
+                "link"
+            } else {
+                "file"
+            },
+            name: &entry.name,
+            path: entry.path.to_string_lossy().to_string(),
+        });
+        for child in &entry.children {
+            collect(child, Some(id), nodes);
+        }
+    }
+
+    let mut nodes = Vec::new();
+    for entry in entries {
+        collect(entry, None, &mut nodes);
+    }
+    let json = to_json(&nodes, config.json_pretty).map_err(io::Error::other)?;
+    writeln!(writer, "{}", json)
+}
+
+/// Print tree as a GitHub-flavored Markdown nested list
+fn print_tree_markdown<W: Write>(writer: &mut W, entry: &TreeEntry) -> io::Result<()> {
+    for (depth, node) in entry.iter() {
+        let indent = "  ".repeat(depth);
+        let suffix = if node.is_dir { "/" } else { "" };
+        write!(writer, "{}- {}{}", indent, node.name, suffix)?;
+        if let Some(ref target) = node.symlink_target {
+            write!(writer, " -> {}", target.display())?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Print tree in TOON (Token-Oriented Object Notation) format
+/// TOON is optimized for LLMs with minimal token usage
+fn print_tree_toon<W: Write>(
+    writer: &mut W,
+    roots: &[(&TreeEntry, &TreeStats)],
+    config: &PrintConfig,
+) -> io::Result<()> {
+    writeln!(writer, "# TOON - Tree Output")?;
+    // Record where each tree starts and whether it was cut off by -L
+    for (entry, stats) in roots {
+        let depth = stats
+            .depth_limit
+            .map_or_else(|| "unlimited".to_string(), |depth| depth.to_string());
+        writeln!(writer, "# root={} depth={}", entry.path.display(), depth)?;
+        print_toon_entry(writer, entry, 0, config)?;
+    }
+    Ok(())
+}
+
+fn print_toon_entry<W: Write>(writer: &mut W, entry: &TreeEntry, depth: usize, config: &PrintConfig) -> io::Result<()> {
     let indent = "  ".repeat(depth);
     let node_type = if entry.is_dir {
         "d"
@@ -293,22 +1644,47 @@ fn print_toon_entry<W: Write>(writer: &mut W, entry: &TreeEntry, depth: usize, c
     // Build metadata parts
     let mut parts: Vec<String> = vec![node_type.to_string()];
 
+    if config.show_depth {
+        parts.push(entry.depth.to_string());
+    }
+
+    if config.show_inode {
+        parts.push(
+            entry
+                .inode()
+                .map_or_else(|| "?".to_string(), |i| i.to_string()),
+        );
+    }
+
     if config.show_permissions {
         parts.push(entry.permissions_string());
     }
 
+    if config.show_owner {
+        parts.push(
+            entry
+                .uid()
+                .map(user_name)
+                .unwrap_or_else(|| "?".to_string()),
+        );
+    }
+
+    if config.show_group {
+        parts.push(
+            entry
+                .gid()
+                .map(group_name)
+                .unwrap_or_else(|| "?".to_string()),
+        );
+    }
+
     if config.show_size {
-        let size_str = if config.human_readable {
-            format_size(entry.size(), config.si_units)
-        } else {
-            entry.size().to_string()
-        };
-        parts.push(size_str);
+        parts.push(size_column(entry, config));
     }
 
     if config.show_date {
         if let Some(time) = entry.modified() {
-            let time_str = format_time(time, config.time_format.as_deref());
+            let time_str = format_entry_time(time, config);
             parts.push(time_str);
         }
     }
@@ -333,3 +1709,1283 @@ fn print_toon_entry<W: Write>(writer: &mut W, entry: &TreeEntry, depth: usize, c
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{walk_directory, walk_streaming, TreeConfig};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn entry(name: &str, is_dir: bool, children: Vec<TreeEntry>) -> TreeEntry {
+        TreeEntry {
+            path: PathBuf::from(name),
+            name: name.to_string(),
+            is_dir,
+            is_symlink: false,
+            symlink_target: None,
+            metadata: None,
+            children,
+            error: None,
+            note: None,
+            unread: false,
+            aggregate_size: None,
+            omitted: 0,
+            depth: 0,
+        }
+    }
+
+    fn render(tree: &TreeEntry, output_format: OutputFormat) -> String {
+        let config = PrintConfig {
+            colorize: false,
+            output_format,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, tree, &config, &TreeStats::default()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_sanitize_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("tab\there"), "").unwrap();
+        fs::write(root.join("new\nline"), "").unwrap();
+        fs::write(root.join("esc\x1b[31mape"), "").unwrap();
+
+        let mut stats = TreeStats::default();
+        let mut tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        tree.name = "root".to_string();
+        let render_with = |sanitize_names, output_format| {
+            let config = PrintConfig {
+                colorize: false,
+                no_report: true,
+                sanitize_names,
+                output_format,
+                ..PrintConfig::default()
+            };
+            let mut out = Vec::new();
+            print_tree(&mut out, &tree, &config, &stats).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let expected = [
+            "root",
+            "├── esc?[31mape",
+            "├── new?line",
+            "└── tab?here",
+            "",
+        ];
+        assert_eq!(render_with(true, OutputFormat::Text), expected.join("\n"));
+        assert!(render_with(false, OutputFormat::Text).contains("├── new\nline"));
+
+        // JSON keeps the raw name, escaped
+        let json = render_with(true, OutputFormat::Json);
+        assert!(json.contains(r#""new\nline""#));
+        assert!(json.contains(r#""tab\there""#));
+        assert!(json.contains(r#""esc\u001b[31mape""#));
+    }
+
+    #[test]
+    fn test_json_reports_errors() {
+        let mut locked = entry("locked", true, vec![]);
+        locked.error = Some("error opening dir: Permission denied (os error 13)".to_string());
+        let tree = entry("root", true, vec![locked, entry("ok", false, vec![])]);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&tree, OutputFormat::Json)).unwrap();
+        let contents = &json["root"]["contents"];
+        assert_eq!(
+            contents[0]["error"],
+            "error opening dir: Permission denied (os error 13)"
+        );
+        assert!(contents[1].get("error").is_none());
+
+        let ndjson = render(&tree, OutputFormat::JsonLines);
+        let locked: serde_json::Value =
+            serde_json::from_str(ndjson.lines().nth(1).unwrap()).unwrap();
+        assert_eq!(locked["name"], "locked");
+        assert!(locked["error"]
+            .as_str()
+            .unwrap()
+            .contains("Permission denied"));
+
+        // Snapshots keep the error when read back
+        let snapshot = read_json_snapshot(render(&tree, OutputFormat::Json).as_bytes()).unwrap();
+        assert!(snapshot[0].children[0].error.is_some());
+        assert_eq!(TreeStats::from_tree(&snapshot[0]).errors, 1);
+    }
+
+    #[test]
+    fn test_no_indent_full_path() {
+        let at = |path: &str, is_dir, children| TreeEntry {
+            path: PathBuf::from(path),
+            ..entry(path.rsplit('/').next().unwrap(), is_dir, children)
+        };
+        let mut locked = at("/p/src/locked", true, vec![]);
+        locked.error = Some("error opening dir: Permission denied".to_string());
+        let tree = at(
+            "/p",
+            true,
+            vec![
+                at(
+                    "/p/src",
+                    true,
+                    vec![at("/p/src/main.rs", false, vec![]), locked],
+                ),
+                at("/p/README", false, vec![]),
+            ],
+        );
+        let config = PrintConfig {
+            colorize: false,
+            no_indent: true,
+            full_path: true,
+            no_report: true,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &TreeStats::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "/p\n/p/src\n/p/src/main.rs\n\
+             /p/src/locked error opening dir: Permission denied\n/p/README\n"
+        );
+    }
+
+    #[test]
+    fn test_full_path_relative_to() {
+        let config = PrintConfig {
+            full_path: true,
+            relative_to: Some(PathBuf::from("/home/user")),
+            ..PrintConfig::default()
+        };
+        assert_eq!(
+            full_path(Path::new("/home/user/src/main.rs"), &config),
+            "src/main.rs"
+        );
+        assert_eq!(full_path(Path::new("/home/user"), &config), ".");
+        // Outside the base, and only whole components count as inside it
+        assert_eq!(full_path(Path::new("/etc/hosts"), &config), "/etc/hosts");
+        assert_eq!(
+            full_path(Path::new("/home/username/notes"), &config),
+            "/home/username/notes"
+        );
+
+        let tree = TreeEntry {
+            path: PathBuf::from("/home/user/project"),
+            ..entry(
+                "project",
+                true,
+                vec![TreeEntry {
+                    path: PathBuf::from("/home/user/project/README"),
+                    ..entry("README", false, vec![])
+                }],
+            )
+        };
+        let config = PrintConfig {
+            colorize: false,
+            no_report: true,
+            ..config
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &TreeStats::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "project\n└── project/README\n"
+        );
+    }
+
+    #[test]
+    fn test_json_versioned_document() {
+        let tree = entry(
+            "root",
+            true,
+            vec![entry("src", true, vec![entry("main.rs", false, vec![])])],
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&tree, OutputFormat::Json)).unwrap();
+        assert_eq!(json["version"], JSON_SCHEMA_VERSION);
+        assert_eq!(json["root"]["name"], "root");
+        assert_eq!(json["root"]["contents"][0]["name"], "src");
+        assert_eq!(json["root"]["contents"][0]["contents"][0]["type"], "file");
+        assert!(json.get("roots").is_none());
+
+        let print = |roots: &[(&TreeEntry, &TreeStats)], json_legacy| {
+            let config = PrintConfig {
+                output_format: OutputFormat::Json,
+                json_legacy,
+                ..PrintConfig::default()
+            };
+            let mut out = Vec::new();
+            print_trees(&mut out, roots, &config).unwrap();
+            out
+        };
+        let stats = TreeStats::default();
+        let other = entry("other", true, vec![]);
+
+        // Several roots are listed under `roots` instead
+        let both = print(&[(&tree, &stats), (&other, &stats)], false);
+        let json: serde_json::Value = serde_json::from_slice(&both).unwrap();
+        assert!(json.get("root").is_none());
+        assert_eq!(json["roots"][1]["name"], "other");
+
+        let legacy = print(&[(&tree, &stats)], true);
+        let json: serde_json::Value = serde_json::from_slice(&legacy).unwrap();
+        assert_eq!(json[0]["name"], "root");
+
+        // Snapshots read back in any layout, but not from a newer version
+        for out in [both.as_slice(), legacy.as_slice()] {
+            assert!(!read_json_snapshot(out).unwrap().is_empty());
+        }
+        assert_eq!(read_json_snapshot(both.as_slice()).unwrap().len(), 2);
+        let future = r#"{"version": 2, "root": {"type": "directory", "name": "x"}}"#;
+        assert!(read_json_snapshot(future.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_max_name_width() {
+        assert_eq!(truncate_name("short.rs".to_string(), 10), "short.rs");
+        assert_eq!(truncate_name("exactly_10".to_string(), 10), "exactly_10");
+        assert_eq!(
+            truncate_name("a_long_name.rs".to_string(), 10),
+            "a_long_na…"
+        );
+        // Multibyte characters count once and are never split
+        assert_eq!(
+            truncate_name("報告書_最終版_改訂.txt".to_string(), 6),
+            "報告書_最…"
+        );
+        assert_eq!(truncate_name("🦀🦀🦀".to_string(), 2), "🦀…");
+
+        let long = "非常に長いファイル名の例です.txt";
+        let tree = entry("root", true, vec![entry(long, false, vec![])]);
+        let config = PrintConfig {
+            colorize: true,
+            no_report: true,
+            max_name_width: Some(8),
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &TreeStats::default()).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains("└── 非常に長いファ…"));
+
+        // JSON keeps the full name
+        let config = PrintConfig {
+            output_format: OutputFormat::Json,
+            ..config
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &TreeStats::default()).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains(long));
+    }
+
+    #[test]
+    fn test_json_compact() {
+        let tree = entry(
+            "root",
+            true,
+            vec![entry("src", true, vec![entry("main.rs", false, vec![])])],
+        );
+        let config = PrintConfig {
+            colorize: false,
+            output_format: OutputFormat::Json,
+            json_pretty: false,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &TreeStats::default()).unwrap();
+        let compact = String::from_utf8(out).unwrap();
+
+        assert_eq!(compact.lines().count(), 1);
+        assert!(compact.ends_with("}\n"));
+        let pretty = render(&tree, OutputFormat::Json);
+        assert!(pretty.lines().count() > 1);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&compact).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&pretty).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_toon_header() {
+        let tree = entry("/x", true, vec![entry("a", false, vec![])]);
+        let toon = render(&tree, OutputFormat::Toon);
+        let mut lines = toon.lines();
+        assert_eq!(lines.next(), Some("# TOON - Tree Output"));
+        assert_eq!(lines.next(), Some("# root=/x depth=unlimited"));
+
+        let config = PrintConfig {
+            output_format: OutputFormat::Toon,
+            ..PrintConfig::default()
+        };
+        let stats = TreeStats {
+            depth_limit: Some(3),
+            ..TreeStats::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        let toon = String::from_utf8(out).unwrap();
+        assert_eq!(toon.lines().nth(1), Some("# root=/x depth=3"));
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/util")).unwrap();
+        fs::write(root.join("src/util/helpers.rs"), "").unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+        fs::write(root.join("README.md"), "").unwrap();
+        std::os::unix::fs::symlink("README.md", root.join("link")).unwrap();
+
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        let json = |tree: &TreeEntry| {
+            let mut out = Vec::new();
+            let config = PrintConfig {
+                output_format: OutputFormat::Json,
+                ..PrintConfig::default()
+            };
+            print_tree(&mut out, tree, &config, &stats).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        for (charset, indent_width) in [(LineCharset::Unicode, 4), (LineCharset::Ascii, 3)] {
+            let config = PrintConfig {
+                colorize: false,
+                charset,
+                indent_width,
+                ..PrintConfig::default()
+            };
+            let mut text = Vec::new();
+            print_tree(&mut text, &tree, &config, &stats).unwrap();
+
+            let parsed = read_text_tree(text.as_slice()).unwrap();
+            assert_eq!(parsed.len(), 1);
+            assert_eq!(json(&parsed[0]), json(&tree));
+        }
+    }
+
+    #[test]
+    fn test_metadata_columns_align() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("dir/nested")).unwrap();
+        fs::write(root.join("alpha"), "x").unwrap();
+        fs::write(root.join("dir/beta"), "x".repeat(123_456)).unwrap();
+        fs::set_permissions(root.join("alpha"), fs::Permissions::from_mode(0o4755)).unwrap();
+        std::os::unix::fs::symlink("missing", root.join("zlink")).unwrap();
+
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        let config = PrintConfig {
+            colorize: false,
+            no_report: true,
+            show_permissions: true,
+            show_size: true,
+            show_date: true,
+            relative_time: true,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        // Offset of the name within the metadata block, after the tree glyphs
+        let offset = |name: &str| {
+            let line = out.lines().skip(1).find(|l| l.contains(name)).unwrap();
+            let glyphs = line.find("── ").unwrap() + "── ".len();
+            line[glyphs..line.find(name).unwrap()].chars().count()
+        };
+        let first = offset("alpha");
+        for name in ["dir", "beta", "nested", "zlink"] {
+            assert_eq!(offset(name), first, "{} in\n{}", name, out);
+        }
+    }
+
+    #[test]
+    fn test_size_units_matrix() {
+        let mut file = entry("file", false, vec![]);
+        file.aggregate_size = Some(1500);
+        let tree = entry("root", true, vec![file]);
+        let stats = TreeStats {
+            files: 1,
+            total_size: 1500,
+            ..TreeStats::default()
+        };
+
+        let text = |human_readable, si_units| {
+            let config = PrintConfig {
+                colorize: false,
+                show_size: true,
+                human_readable,
+                si_units,
+                ..PrintConfig::default()
+            };
+            let mut out = Vec::new();
+            print_tree(&mut out, &tree, &config, &stats).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        // -s, -h and --si (which implies -h) format the column and the total
+        // the same way
+        assert!(text(false, false).contains("└── 1500 file"));
+        assert!(text(false, false).contains("1 file, 1500 total"));
+        assert!(text(true, false).contains("└── 1.5K file"));
+        assert!(text(true, false).contains("1 file, 1.5K total"));
+        assert!(text(true, true).contains("└── 1.5kB file"));
+        assert!(text(true, true).contains("1 file, 1.5kB total"));
+    }
+
+    #[test]
+    fn test_broken_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("file"), "").unwrap();
+        std::os::unix::fs::symlink("file", root.join("good")).unwrap();
+        std::os::unix::fs::symlink("missing", root.join("bad")).unwrap();
+
+        let mut stats = TreeStats::default();
+        let mut tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        let bad = &tree.children[0];
+        assert!(bad.is_broken_symlink());
+        assert_eq!(bad.size(), 0);
+        assert!(!tree.children[2].is_broken_symlink());
+
+        tree.name = "root".to_string();
+        let config = PrintConfig {
+            colorize: false,
+            no_report: true,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        let expected = [
+            "root",
+            "├── bad -> missing [broken]",
+            "├── file",
+            "└── good -> file",
+            "",
+        ];
+        assert_eq!(String::from_utf8(out).unwrap(), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_compact_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/empty")).unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+        fs::write(root.join("src/lib.rs"), "").unwrap();
+        fs::write(root.join("Cargo.toml"), "").unwrap();
+
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        let root = root.display();
+        let expected = [
+            format!("{}/: Cargo.toml, src", root),
+            format!("{}/src/: empty, lib.rs, main.rs", root),
+            format!("{}/src/empty/:", root),
+            String::new(),
+        ];
+        assert_eq!(render(&tree, OutputFormat::Compact), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_ascii_charset() {
+        let tree = entry(
+            "root",
+            true,
+            vec![
+                entry("src", true, vec![entry("main.rs", false, vec![])]),
+                entry("Cargo.toml", false, vec![]),
+            ],
+        );
+        let config = PrintConfig {
+            colorize: false,
+            no_report: true,
+            charset: LineCharset::Ascii,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &TreeStats::default()).unwrap();
+
+        let expected = ["root", "|-- src", "|   `-- main.rs", "`-- Cargo.toml", ""];
+        assert_eq!(String::from_utf8(out).unwrap(), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_inode_column() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("file"), "").unwrap();
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(dir.path(), &TreeConfig::default(), &mut stats, 0);
+        let config = PrintConfig {
+            colorize: false,
+            no_report: true,
+            show_inode: true,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let line = out.lines().nth(1).unwrap();
+        let mut columns = line.trim_start_matches("└── ").split_whitespace();
+        let inode: u64 = columns.next().unwrap().parse().unwrap();
+        assert_eq!(Some(inode), tree.children[0].inode());
+        assert_eq!(columns.next(), Some("file"));
+
+        let json_config = PrintConfig {
+            output_format: OutputFormat::Json,
+            ..config
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &json_config, &stats).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains(&format!("\"inode\": {}", inode)));
+    }
+
+    #[test]
+    fn test_checksum_column() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub/abc.txt"), "abc").unwrap();
+        std::os::unix::fs::symlink("sub/abc.txt", dir.path().join("link")).unwrap();
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(dir.path(), &TreeConfig::default(), &mut stats, 0);
+        let config = PrintConfig {
+            colorize: false,
+            no_report: true,
+            checksum: Some(ChecksumAlgorithm::Crc32),
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().skip(1).collect();
+        // Directories and symlinks get a blank column of the same width
+        assert_eq!(
+            lines,
+            vec![
+                "├──          link -> sub/abc.txt",
+                "└──          sub",
+                "    └── 352441c2 abc.txt",
+            ]
+        );
+
+        let sha = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let json_config = PrintConfig {
+            output_format: OutputFormat::Json,
+            checksum: Some(ChecksumAlgorithm::Sha256),
+            ..config
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &json_config, &stats).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(&format!("\"checksum\": \"{}\"", sha)));
+        assert_eq!(out.matches("\"checksum\"").count(), 1);
+    }
+
+    #[test]
+    fn test_flat_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        std::fs::write(root.join("a/b/file"), "").unwrap();
+        std::fs::write(root.join("top"), "").unwrap();
+
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        let out = render(&tree, OutputFormat::Flat);
+
+        let expected: Vec<PathBuf> = ["", "a", "a/b", "a/b/file", "top"]
+            .iter()
+            .map(|p| root.join(p).components().collect())
+            .collect();
+        let actual: Vec<PathBuf> = out.lines().map(PathBuf::from).collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_markdown_output() {
+        let mut link = entry("latest", false, vec![]);
+        link.is_symlink = true;
+        link.symlink_target = Some(PathBuf::from("v1"));
+        let tree = entry(
+            "root",
+            true,
+            vec![
+                entry("src", true, vec![entry("main.rs", false, vec![])]),
+                link,
+            ],
+        );
+
+        let expected = [
+            "- root/",
+            "  - src/",
+            "    - main.rs",
+            "  - latest -> v1",
+            "",
+        ];
+        assert_eq!(render(&tree, OutputFormat::Markdown), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_report_format() {
+        let stats = TreeStats {
+            directories: 3,
+            files: 12,
+            total_size: 5 * 1024 * 1024,
+            total_lines: 420,
+            ..TreeStats::default()
+        };
+        assert_eq!(render_report("{dirs}d {files}f", &stats, "5.0M"), "3d 12f");
+        assert_eq!(render_report("{lines} lines", &stats, "5.0M"), "420 lines");
+        assert_eq!(
+            render_report("size={size} {unknown} {dirs", &stats, "5.0M"),
+            "size=5.0M {unknown} {dirs"
+        );
+
+        let tree = entry("root", true, vec![]);
+        let mut config = PrintConfig {
+            colorize: false,
+            human_readable: true,
+            report_format: Some("{files} files, {size}".to_string()),
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "root\n\n12 files, 5.0M\n");
+
+        config.no_report = true;
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "root\n");
+    }
+
+    #[test]
+    fn test_git_status_column() {
+        let tree = entry(
+            "root",
+            true,
+            vec![entry("a.rs", false, vec![]), entry("b.rs", false, vec![])],
+        );
+        let config = PrintConfig {
+            colorize: false,
+            no_report: true,
+            git_status: Some([("b.rs".into(), 'M')].into_iter().collect()),
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &TreeStats::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "root\n├──   a.rs\n└── M b.rs\n"
+        );
+    }
+
+    #[test]
+    fn test_report_errors() {
+        let tree = entry("root", true, vec![]);
+        let config = PrintConfig {
+            colorize: false,
+            ..PrintConfig::default()
+        };
+        let mut stats = TreeStats {
+            directories: 3,
+            files: 4,
+            errors: 1,
+            ..TreeStats::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "root\n\n3 directories, 4 files, 1 directory unreadable\n"
+        );
+
+        stats.errors = 0;
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "root\n\n3 directories, 4 files\n"
+        );
+    }
+
+    #[test]
+    fn test_note_is_not_an_error() {
+        let mut up = entry("up", true, vec![]);
+        up.note = Some("recursive, not followed".to_string());
+        let tree = entry("root", true, vec![entry("sub", true, vec![up])]);
+        let stats = TreeStats::from_tree(&tree);
+        assert_eq!(stats.errors, 0);
+
+        let config = PrintConfig {
+            colorize: false,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "root\n└── sub\n    └── up\n        [recursive, not followed]\n\n2 directories, 0 files\n"
+        );
+
+        let mut out = Vec::new();
+        print_tree_json_lines(&mut out, &tree, &config).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(r#""note":"recursive, not followed""#));
+        assert!(!out.contains("error"));
+    }
+
+    #[test]
+    fn test_size_column_auto_width() {
+        let sized = |name: &str, size: u64| {
+            let mut file = entry(name, false, vec![]);
+            file.aggregate_size = Some(size);
+            file
+        };
+        let mut dir = entry("dir", true, vec![sized("small", 7)]);
+        dir.aggregate_size = Some(4096);
+        let tree = entry("root", true, vec![sized("big", 12_345_678_901), dir]);
+
+        let config = PrintConfig {
+            colorize: false,
+            show_size: true,
+            no_report: true,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &TreeStats::default()).unwrap();
+        let expected = [
+            "root",
+            "├── 12345678901 big",
+            "└──        4096 dir",
+            "    └──           7 small",
+            "",
+        ];
+        assert_eq!(String::from_utf8(out).unwrap(), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_json_snapshot_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/util")).unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(root.join("src/util/mod.rs"), "").unwrap();
+        fs::write(root.join("README"), "hello").unwrap();
+        std::os::unix::fs::symlink("README", root.join("link")).unwrap();
+
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        let config = PrintConfig {
+            colorize: false,
+            show_size: true,
+            ..PrintConfig::default()
+        };
+        let text = |tree: &TreeEntry, stats: &TreeStats| {
+            let mut out = Vec::new();
+            print_tree(&mut out, tree, &config, stats).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let mut json = Vec::new();
+        let json_config = PrintConfig {
+            output_format: OutputFormat::Json,
+            ..config.clone()
+        };
+        print_tree(&mut json, &tree, &json_config, &stats).unwrap();
+
+        let restored = read_json_snapshot(json.as_slice()).unwrap();
+        assert_eq!(restored.len(), 1);
+        let restored_stats = TreeStats::from_tree(&restored[0]);
+        assert_eq!(text(&restored[0], &restored_stats), text(&tree, &stats));
+    }
+
+    #[test]
+    fn test_child_count() {
+        let tree = entry(
+            "root",
+            true,
+            vec![
+                entry("empty", true, vec![]),
+                entry(
+                    "src",
+                    true,
+                    vec![entry("a.rs", false, vec![]), entry("b.rs", false, vec![])],
+                ),
+                TreeEntry {
+                    unread: true,
+                    ..entry("deep", true, vec![])
+                },
+            ],
+        );
+        let config = PrintConfig {
+            colorize: false,
+            show_child_count: true,
+            show_type_indicator: true,
+            no_report: true,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &TreeStats::default()).unwrap();
+        let expected = [
+            "root/ [3]",
+            "├── empty/ [0]",
+            "├── src/ [2]",
+            "│   ├── a.rs",
+            "│   └── b.rs",
+            "└── deep/",
+            "",
+        ];
+        assert_eq!(String::from_utf8(out).unwrap(), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let tree = entry(
+            "root",
+            true,
+            vec![entry("src", true, vec![entry("main.rs", false, vec![])])],
+        );
+        let out = render(&tree, OutputFormat::JsonLines);
+        let lines: Vec<serde_json::Value> = out
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["depth"], 0);
+        assert_eq!(lines[0]["type"], "directory");
+        assert_eq!(lines[1]["name"], "src");
+        assert_eq!(lines[2]["depth"], 2);
+        assert_eq!(lines[2]["type"], "file");
+        assert_eq!(lines[2]["path"], "main.rs");
+        assert!(lines[2].get("size").is_none());
+    }
+
+    #[test]
+    fn test_json_lines_streamed() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src/bin")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "").unwrap();
+        let config = PrintConfig {
+            output_format: OutputFormat::JsonLines,
+            show_size: true,
+            ..PrintConfig::default()
+        };
+
+        // Written entry by entry from the walk, the lines match a printed tree
+        let mut streamed = Vec::new();
+        walk_streaming(dir.path(), &TreeConfig::default(), |entry, depth| {
+            write_json_line(&mut streamed, entry, depth, &config).unwrap();
+        });
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(dir.path(), &TreeConfig::default(), &mut stats, 0);
+        let mut printed = Vec::new();
+        print_tree(&mut printed, &tree, &config, &stats).unwrap();
+        assert_eq!(
+            String::from_utf8(streamed).unwrap(),
+            String::from_utf8(printed).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_json_flat() {
+        let tree = entry(
+            "root",
+            true,
+            vec![
+                entry("src", true, vec![entry("main.rs", false, vec![])]),
+                entry("lib", true, vec![entry("a.rs", false, vec![])]),
+                entry("README.md", false, vec![]),
+            ],
+        );
+        let out = render(&tree, OutputFormat::JsonFlat);
+        let nodes: Vec<serde_json::Value> = serde_json::from_str(&out).unwrap();
+        assert_eq!(nodes.len(), 6);
+        assert!(nodes[0]["parent_id"].is_null());
+        assert_eq!(nodes[0]["type"], "directory");
+
+        // Following parent ids gives back every entry's place in the tree
+        fn rebuild(nodes: &[serde_json::Value], id: u64) -> TreeEntry {
+            let node = &nodes[id as usize];
+            let children = nodes
+                .iter()
+                .filter(|n| n["parent_id"].as_u64() == Some(id))
+                .map(|n| rebuild(nodes, n["id"].as_u64().unwrap()))
+                .collect();
+            entry(
+                node["name"].as_str().unwrap(),
+                node["type"] == "directory",
+                children,
+            )
+        }
+        let rebuilt = rebuild(&nodes, 0);
+        assert_eq!(
+            render(&rebuilt, OutputFormat::Text),
+            render(&tree, OutputFormat::Text)
+        );
+    }
+
+    #[test]
+    fn test_html_output() {
+        let tree = entry(
+            "root",
+            true,
+            vec![
+                entry(
+                    "a<b>",
+                    true,
+                    vec![entry("Tom & \"Jerry\".txt", false, vec![])],
+                ),
+                entry("empty", true, vec![]),
+            ],
+        );
+        let expected = [
+            "<ul class=\"tree\">",
+            "  <li class=\"tree-dir\"><details open><summary>root</summary>",
+            "    <ul>",
+            "      <li class=\"tree-dir\"><details open><summary>a&lt;b&gt;</summary>",
+            "        <ul>",
+            "          <li class=\"tree-file\">Tom &amp; &quot;Jerry&quot;.txt</li>",
+            "        </ul>",
+            "      </details></li>",
+            "      <li class=\"tree-dir\"><details open><summary>empty</summary></details></li>",
+            "    </ul>",
+            "  </details></li>",
+            "</ul>",
+            "",
+        ];
+        assert_eq!(render(&tree, OutputFormat::Html), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_indent_width() {
+        let tree = entry(
+            "root",
+            true,
+            vec![
+                entry("a", true, vec![entry("x", false, vec![])]),
+                entry("b", true, vec![entry("y", false, vec![])]),
+            ],
+        );
+        let render_width = |indent_width, charset| {
+            let config = PrintConfig {
+                colorize: false,
+                no_report: true,
+                indent_width,
+                charset,
+                ..PrintConfig::default()
+            };
+            let mut out = Vec::new();
+            print_tree(&mut out, &tree, &config, &TreeStats::default()).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let expected = ["root", "├── a", "│   └── x", "└── b", "    └── y", ""];
+        assert_eq!(render_width(4, LineCharset::Unicode), expected.join("\n"));
+
+        let expected = ["root", "├ a", "│ └ x", "└ b", "  └ y", ""];
+        assert_eq!(render_width(2, LineCharset::Unicode), expected.join("\n"));
+
+        let expected = [
+            "root",
+            "|---- a",
+            "|     `---- x",
+            "`---- b",
+            "      `---- y",
+            "",
+        ];
+        assert_eq!(render_width(6, LineCharset::Ascii), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_size_bar() {
+        assert_eq!(size_bar(40, 100), "[####      ]  40%");
+        assert_eq!(size_bar(100, 100), "[##########] 100%");
+        assert_eq!(size_bar(0, 0), "[          ]   0%");
+
+        let sized = |name: &str, size: u64| {
+            let mut file = entry(name, false, vec![]);
+            file.aggregate_size = Some(size);
+            file
+        };
+        let mut tree = entry("root", true, vec![sized("a", 300), sized("b", 100)]);
+        tree.aggregate_size = Some(400);
+        let config = PrintConfig {
+            colorize: false,
+            no_report: true,
+            show_size_bar: true,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &TreeStats::default()).unwrap();
+        let expected = [
+            "root",
+            "├── [########  ]  75% a",
+            "└── [###       ]  25% b",
+            "",
+        ];
+        assert_eq!(String::from_utf8(out).unwrap(), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_count_only() {
+        let tree = entry(
+            "root",
+            true,
+            vec![entry("dir", true, vec![]), entry("file", false, vec![])],
+        );
+        let stats = TreeStats {
+            directories: 1,
+            files: 1,
+            ..TreeStats::default()
+        };
+        let config = PrintConfig {
+            colorize: false,
+            count_only: true,
+            output_format: OutputFormat::Json,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "1 directory, 1 file\n");
+
+        let config = PrintConfig {
+            no_report: true,
+            ..config
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_stats_by_ext() {
+        let stats = TreeStats {
+            files: 6,
+            extensions: [("rs", 3), ("toml", 2), ("", 1)]
+                .into_iter()
+                .map(|(ext, count)| (ext.to_string(), count))
+                .collect(),
+            ..TreeStats::default()
+        };
+        let tree = entry("root", true, vec![]);
+        let mut config = PrintConfig {
+            colorize: false,
+            ..PrintConfig::default()
+        };
+
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "root\n\n0 directories, 6 files\n"
+        );
+
+        config.stats_by_ext = true;
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "root\n\n0 directories, 6 files\n3 .rs, 2 .toml, 1 no-ext\n"
+        );
+
+        config.stats_by_ext = false;
+        config.show_depth_stats = true;
+        let stats = TreeStats {
+            max_depth_reached: 3,
+            listed_dirs: 3,
+            listed_children: 7,
+            ..stats
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("6 files\nmax depth 3, 2.33 entries per directory\n"));
+    }
+
+    #[test]
+    fn test_forced_color_resets() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub/file"), "").unwrap();
+        std::os::unix::fs::symlink("missing", dir.path().join("broken")).unwrap();
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(dir.path(), &TreeConfig::default(), &mut stats, 0);
+        let config = PrintConfig {
+            colorize: true,
+            show_size: true,
+            show_permissions: true,
+            show_child_count: true,
+            ..PrintConfig::default()
+        };
+
+        // As when --color is forced into a pipe
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &stats).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let colored_lines: Vec<_> = out.lines().filter(|l| l.contains('\x1b')).collect();
+        assert_eq!(colored_lines.len(), 4);
+        for line in colored_lines {
+            // The last escape on the line is a reset
+            let last = line.rfind('\x1b').unwrap();
+            assert!(line[last..].starts_with("\x1b[0m"), "{:?}", line);
+            // Every segment opened is closed again
+            let opened = line.matches("\x1b[").count() - line.matches("\x1b[0m").count();
+            assert_eq!(opened, line.matches("\x1b[0m").count(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_size_column_color() {
+        let mut file = entry("file", false, vec![]);
+        file.aggregate_size = Some(42);
+        let tree = entry("root", true, vec![file]);
+        let mut config = PrintConfig {
+            colorize: true,
+            no_report: true,
+            show_size: true,
+            ..PrintConfig::default()
+        };
+
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &TreeStats::default()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\x1b[32m42\x1b[0m file"));
+
+        config.colorize = false;
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &config, &TreeStats::default()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(!out.contains('\x1b'));
+        assert!(out.contains("42 file"));
+    }
+
+    #[test]
+    fn test_yaml_output() {
+        let tree = entry(
+            "root",
+            true,
+            vec![
+                entry("src", true, vec![entry("main.rs", false, vec![])]),
+                entry("empty", true, vec![]),
+            ],
+        );
+        let yaml = render(&tree, OutputFormat::Yaml);
+        let value: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+
+        let root = &value[0];
+        assert_eq!(root["type"], "directory");
+        assert_eq!(root["name"], "root");
+        let src = &root["contents"][0];
+        assert_eq!(src["contents"][0]["type"], "file");
+        assert_eq!(src["contents"][0]["name"], "main.rs");
+        let empty = root["contents"][1].as_mapping().unwrap();
+        assert_eq!(empty["name"], "empty");
+        assert!(!empty.contains_key("contents"));
+        assert!(!empty.contains_key("target"));
+    }
+
+    #[test]
+    fn test_json_size_and_time() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("file"), "x".repeat(123)).unwrap();
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(dir.path(), &TreeConfig::default(), &mut stats, 0);
+        let json = |show_size, show_date| {
+            let config = PrintConfig {
+                show_size,
+                show_date,
+                output_format: OutputFormat::Json,
+                ..PrintConfig::default()
+            };
+            let mut out = Vec::new();
+            print_tree(&mut out, &tree, &config, &stats).unwrap();
+            let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+            value["root"]["contents"][0].clone()
+        };
+
+        let plain = json(false, false);
+        assert!(plain.get("size").is_none());
+        assert!(plain.get("time").is_none());
+
+        let detailed = json(true, true);
+        assert_eq!(detailed["size"], 123);
+        let expected_time = modified_epoch(&tree.children[0]).unwrap();
+        assert_eq!(detailed["time"], expected_time);
+        assert!(expected_time > 1_600_000_000);
+    }
+
+    #[test]
+    fn test_dot_output() {
+        let tree = entry(
+            "root",
+            true,
+            vec![
+                entry("src", true, vec![entry("main.rs", false, vec![])]),
+                entry("say \"hi\"", false, vec![]),
+            ],
+        );
+        let dot = render(&tree, OutputFormat::Dot);
+
+        assert!(dot.starts_with("digraph"));
+        assert!(dot.trim_end().ends_with('}'));
+        assert_eq!(dot.matches(" -> ").count(), 3);
+        assert!(dot.contains(r#"n0 [label="root", shape=folder];"#));
+        assert!(dot.contains(r#"n2 [label="main.rs", shape=note];"#));
+        assert!(dot.contains(r#"n1 -> n2;"#));
+        assert!(dot.contains(r#"[label="say \"hi\"", shape=note]"#));
+    }
+
+    #[test]
+    fn test_xml_output() {
+        let mut link = entry("latest", false, vec![]);
+        link.is_symlink = true;
+        link.symlink_target = Some(PathBuf::from("v1"));
+        let tree = entry(
+            "root",
+            true,
+            vec![
+                entry("src", true, vec![entry("main.rs", false, vec![])]),
+                entry("empty", true, vec![]),
+                link,
+            ],
+        );
+
+        let expected = [
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<tree>"#,
+            r#"  <directory name="root">"#,
+            r#"    <directory name="src">"#,
+            r#"      <file name="main.rs"/>"#,
+            r#"    </directory>"#,
+            r#"    <directory name="empty"/>"#,
+            r#"    <link name="latest" target="v1"/>"#,
+            r#"  </directory>"#,
+            r#"</tree>"#,
+            "",
+        ];
+        assert_eq!(render(&tree, OutputFormat::Xml), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_xml_escaping() {
+        let tree = entry("root", true, vec![entry("a&b <\"c\">", false, vec![])]);
+        let xml = render(&tree, OutputFormat::Xml);
+        assert!(xml.contains(r#"<file name="a&amp;b &lt;&quot;c&quot;&gt;"/>"#));
+    }
+}
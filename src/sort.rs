@@ -1,79 +1,422 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
 use crate::tree::TreeEntry;
 
 /// Sort key options
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum SortKey {
     #[default]
     Name,
     Size,
+    /// Subtree size for directories, largest first
+    TotalSize,
+    #[serde(alias = "mtime")]
     Time,
+    /// Inode change time
+    Ctime,
+    #[serde(alias = "ext")]
+    Extension,
+    /// Shuffled; reproducible with [`Sorter::seed`]
+    Random,
     None,
 }
 
 impl SortKey {
+    /// Parse a `--sort` value; unknown keys fall back to [`SortKey::Name`]
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         match s.to_lowercase().as_str() {
             "name" => SortKey::Name,
             "size" => SortKey::Size,
+            "total-size" | "totalsize" => SortKey::TotalSize,
             "mtime" | "time" => SortKey::Time,
+            "ctime" => SortKey::Ctime,
+            "extension" | "ext" => SortKey::Extension,
+            "random" => SortKey::Random,
             "none" => SortKey::None,
             _ => SortKey::Name,
         }
     }
 }
 
+/// Whether directories and files are grouped apart before sorting
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GroupOrder {
+    /// Directories and files are interleaved
+    #[default]
+    None,
+    DirsFirst,
+    FilesFirst,
+}
+
 /// Sorter for tree entries
 pub struct Sorter {
     key: SortKey,
     reverse: bool,
-    dirs_first: bool,
+    group: GroupOrder,
+    case_sensitive: bool,
+    locale_aware: bool,
+    seed: u64,
 }
 
 impl Sorter {
-    pub fn new(key: SortKey, reverse: bool, dirs_first: bool) -> Self {
+    pub fn new(key: SortKey, reverse: bool, group: GroupOrder) -> Self {
         Self {
             key,
             reverse,
-            dirs_first,
+            group,
+            case_sensitive: false,
+            locale_aware: false,
+            seed: RandomState::new().build_hasher().finish(),
+        }
+    }
+
+    /// Seed for [`SortKey::Random`], so the same seed gives the same order;
+    /// without one a random seed is drawn
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Position of an entry in the shuffle, derived from its path so the
+    /// order doesn't depend on how the entries were listed
+    fn random_key(&self, entry: &TreeEntry) -> u64 {
+        // FNV-1a over the path, then a splitmix64 round to spread the bits
+        let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+        for byte in entry.path.as_os_str().as_encoded_bytes() {
+            hash = (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+        let mut z = (hash ^ self.seed).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Compare names byte-wise (uppercase before lowercase, like the C
+    /// locale) instead of ignoring case
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    /// Collate accented Latin letters with their base letter, so `é` sorts
+    /// among the `e`s rather than after `z`
+    pub fn locale_aware(mut self, locale_aware: bool) -> Self {
+        self.locale_aware = locale_aware;
+        self
+    }
+
+    fn compare_names(&self, a: &str, b: &str) -> std::cmp::Ordering {
+        // Pure ASCII names collate the same either way
+        if self.locale_aware && !(a.is_ascii() && b.is_ascii()) {
+            return collation_key(a)
+                .cmp(&collation_key(b))
+                .then_with(|| self.compare_plain(a, b));
+        }
+        self.compare_plain(a, b)
+    }
+
+    fn compare_plain(&self, a: &str, b: &str) -> std::cmp::Ordering {
+        if self.case_sensitive {
+            a.cmp(b)
+        } else {
+            a.to_lowercase().cmp(&b.to_lowercase())
         }
     }
 
     pub fn sort(&self, entries: &mut [TreeEntry]) {
-        if matches!(self.key, SortKey::None) && !self.dirs_first {
+        if matches!(self.key, SortKey::None) && self.group == GroupOrder::None {
             return;
         }
 
+        // Subtree sizes are worked out once per entry rather than per comparison
+        let totals: HashMap<PathBuf, u64> = if matches!(self.key, SortKey::TotalSize) {
+            entries
+                .iter()
+                .map(|e| (e.path.clone(), e.total_size()))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        let total = |entry: &TreeEntry| totals.get(&entry.path).copied().unwrap_or(0);
+
         entries.sort_by(|a, b| {
-            // Dirs first handling
-            if self.dirs_first {
-                match (a.is_dir, b.is_dir) {
-                    (true, false) => return std::cmp::Ordering::Less,
-                    (false, true) => return std::cmp::Ordering::Greater,
-                    _ => {}
+            // Directory/file grouping takes precedence over the sort key
+            if a.is_dir != b.is_dir {
+                match self.group {
+                    GroupOrder::DirsFirst => return b.is_dir.cmp(&a.is_dir),
+                    GroupOrder::FilesFirst => return a.is_dir.cmp(&b.is_dir),
+                    GroupOrder::None => {}
                 }
             }
 
             let ordering = match self.key {
-                SortKey::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+                SortKey::Name => self.compare_names(&a.name, &b.name),
                 SortKey::Size => a.size().cmp(&b.size()),
-                SortKey::Time => {
-                    let a_time = a.modified();
-                    let b_time = b.modified();
-                    match (a_time, b_time) {
-                        (Some(at), Some(bt)) => at.cmp(&bt),
-                        (Some(_), None) => std::cmp::Ordering::Less,
-                        (None, Some(_)) => std::cmp::Ordering::Greater,
-                        (None, None) => std::cmp::Ordering::Equal,
-                    }
-                }
+                SortKey::TotalSize => total(b).cmp(&total(a)),
+                SortKey::Time => compare_times(a.modified(), b.modified()),
+                SortKey::Ctime => compare_times(a.ctime(), b.ctime()),
+                SortKey::Extension => extension(&a.name)
+                    .cmp(&extension(&b.name))
+                    .then_with(|| self.compare_names(&a.name, &b.name)),
+                SortKey::Random => self.random_key(a).cmp(&self.random_key(b)),
                 SortKey::None => std::cmp::Ordering::Equal,
             };
 
-            if self.reverse {
+            let ordering = if self.reverse {
                 ordering.reverse()
             } else {
                 ordering
+            };
+
+            // Break ties on the path so output doesn't depend on the order
+            // `read_dir` happened to return; unsorted listings keep that order
+            if matches!(self.key, SortKey::None) {
+                ordering
+            } else {
+                ordering.then_with(|| a.path.cmp(&b.path))
             }
         });
     }
 }
+
+/// Order known times oldest first, with unknown times last
+fn compare_times(a: Option<SystemTime>, b: Option<SystemTime>) -> std::cmp::Ordering {
+    match (a, b) {
+        (Some(at), Some(bt)) => at.cmp(&bt),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    }
+}
+
+/// Base letters for U+00C0 to U+017F (Latin-1 Supplement and Latin
+/// Extended-A); `\0` marks letters spelled with two (see [`collation_key`])
+/// and symbols left as they are
+const LATIN_FOLD: &[u8; 192] = b"\
+    aaaaaa\0ceeeeiiiidnooooo\0ouuuuy\0\0\
+    aaaaaa\0ceeeeiiiidnooooo\0ouuuuy\0y\
+    aaaaaaccccccccddddeeeeeeeeeegggg\
+    gggghhhhiiiiiiiiii\0\0jjkkklllllll\
+    lllnnnnnnnnnoooooo\0\0rrrrrrssssss\
+    ssttttttuuuuuuuuuuuuwwyyyzzzzzzs";
+
+/// Primary sort key for `--locale-sort`: lowercased, with Latin letters
+/// reduced to their base letters and combining accents (as in decomposed
+/// names, e.g. from macOS) dropped
+fn collation_key(name: &str) -> String {
+    let mut key = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '\u{300}'..='\u{36f}' => {}
+            'Æ' | 'æ' => key.push_str("ae"),
+            'Œ' | 'œ' => key.push_str("oe"),
+            'Þ' | 'þ' => key.push_str("th"),
+            'ß' => key.push_str("ss"),
+            'Ĳ' | 'ĳ' => key.push_str("ij"),
+            '\u{c0}'..='\u{17f}' if LATIN_FOLD[c as usize - 0xc0] != 0 => {
+                key.push(LATIN_FOLD[c as usize - 0xc0] as char)
+            }
+            _ => key.extend(c.to_lowercase()),
+        }
+    }
+    key
+}
+
+/// Lowercased extension of a file name; names without one sort first
+pub(crate) fn extension(name: &str) -> Option<String> {
+    Path::new(name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted_names(names: &[&str], key: SortKey) -> Vec<String> {
+        sort_with(names, Sorter::new(key, false, GroupOrder::None))
+    }
+
+    fn sort_with(names: &[&str], sorter: Sorter) -> Vec<String> {
+        let mut entries: Vec<TreeEntry> = names.iter().map(|n| TreeEntry::new(n.into())).collect();
+        sorter.sort(&mut entries);
+        entries.into_iter().map(|e| e.name).collect()
+    }
+
+    #[test]
+    fn test_case_sensitive_name_sort() {
+        let names = ["banana", "Zebra", "apple", "Mango"];
+        assert_eq!(
+            sorted_names(&names, SortKey::Name),
+            vec!["apple", "banana", "Mango", "Zebra"]
+        );
+        assert_eq!(
+            sort_with(
+                &names,
+                Sorter::new(SortKey::Name, false, GroupOrder::None).case_sensitive(true)
+            ),
+            vec!["Mango", "Zebra", "apple", "banana"]
+        );
+    }
+
+    #[test]
+    fn test_group_order() {
+        let mut entries = vec![
+            TreeEntry::new("b".into()),
+            TreeEntry::new("d1".into()),
+            TreeEntry::new("a".into()),
+            TreeEntry::new("d0".into()),
+        ];
+        entries[1].is_dir = true;
+        entries[3].is_dir = true;
+
+        let sorted = |group| {
+            let mut entries = entries.clone();
+            Sorter::new(SortKey::Name, false, group).sort(&mut entries);
+            entries.into_iter().map(|e| e.name).collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(GroupOrder::None), vec!["a", "b", "d0", "d1"]);
+        assert_eq!(sorted(GroupOrder::DirsFirst), vec!["d0", "d1", "a", "b"]);
+        assert_eq!(sorted(GroupOrder::FilesFirst), vec!["a", "b", "d0", "d1"]);
+
+        // Reversing the key doesn't undo the grouping
+        let mut reversed = entries.clone();
+        Sorter::new(SortKey::Name, true, GroupOrder::FilesFirst).sort(&mut reversed);
+        let names: Vec<_> = reversed.into_iter().map(|e| e.name).collect();
+        assert_eq!(names, vec!["b", "a", "d1", "d0"]);
+    }
+
+    #[test]
+    fn test_ties_break_on_path() {
+        // None of these exist, so sizes and times are all equal
+        let names = ["delta", "bravo", "charlie", "alpha"];
+        let expected = vec!["alpha", "bravo", "charlie", "delta"];
+        assert_eq!(sorted_names(&names, SortKey::Size), expected);
+        assert_eq!(sorted_names(&names, SortKey::TotalSize), expected);
+        assert_eq!(sorted_names(&names, SortKey::Time), expected);
+        assert_eq!(sorted_names(&names, SortKey::Ctime), expected);
+        assert_eq!(
+            sort_with(&names, Sorter::new(SortKey::Size, true, GroupOrder::None)),
+            expected
+        );
+        assert_eq!(sorted_names(&names, SortKey::None), names);
+    }
+
+    #[test]
+    fn test_sort_by_extension() {
+        assert_eq!(
+            sorted_names(&["c.txt", "b.rs", "a.txt", "Makefile"], SortKey::Extension),
+            vec!["Makefile", "b.rs", "a.txt", "c.txt"]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_ctime() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        use std::thread::sleep;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first");
+        let second = dir.path().join("second");
+        fs::write(&first, "").unwrap();
+        sleep(Duration::from_millis(20));
+        fs::write(&second, "").unwrap();
+
+        let sorted = || {
+            let mut entries = vec![
+                TreeEntry::new(second.clone()),
+                TreeEntry::new(first.clone()),
+            ];
+            Sorter::new(SortKey::Ctime, false, GroupOrder::None).sort(&mut entries);
+            entries.into_iter().map(|e| e.name).collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(), vec!["first", "second"]);
+
+        // A permission change bumps ctime but leaves mtime alone
+        sleep(Duration::from_millis(20));
+        fs::set_permissions(&first, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(sorted(), vec!["second", "first"]);
+        assert!(matches!(SortKey::from_str("ctime"), SortKey::Ctime));
+    }
+
+    #[test]
+    fn test_random_sort_is_seeded() {
+        let names: Vec<String> = (0..20).map(|i| format!("file{:02}", i)).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let shuffled = |seed| {
+            sort_with(
+                &names,
+                Sorter::new(SortKey::Random, false, GroupOrder::None).seed(seed),
+            )
+        };
+
+        assert_eq!(shuffled(7), shuffled(7));
+        assert_ne!(shuffled(7), shuffled(8));
+        assert_ne!(shuffled(7), names);
+        // The listing order doesn't matter, only the seed
+        let mut reversed = names.clone();
+        reversed.reverse();
+        assert_eq!(
+            sort_with(
+                &reversed,
+                Sorter::new(SortKey::Random, false, GroupOrder::None).seed(7)
+            ),
+            shuffled(7)
+        );
+        assert!(matches!(SortKey::from_str("random"), SortKey::Random));
+    }
+
+    #[test]
+    fn test_random_sort_keeps_groups() {
+        let mut entries: Vec<TreeEntry> = (0..10)
+            .map(|i| TreeEntry::new(format!("entry{}", i).into()))
+            .collect();
+        for entry in entries.iter_mut().step_by(2) {
+            entry.is_dir = true;
+        }
+        Sorter::new(SortKey::Random, false, GroupOrder::DirsFirst)
+            .seed(3)
+            .sort(&mut entries);
+        let dirs: Vec<bool> = entries.iter().map(|e| e.is_dir).collect();
+        assert_eq!(dirs, [[true; 5], [false; 5]].concat());
+    }
+
+    #[test]
+    fn test_locale_sort() {
+        let names = ["zebra", "éclair", "Apple", "Ébène", "eclipse", "Zoë", "Ærø"];
+        // Byte order puts accented letters after `z`
+        assert_eq!(
+            sorted_names(&names, SortKey::Name),
+            vec!["Apple", "eclipse", "zebra", "Zoë", "Ærø", "Ébène", "éclair"]
+        );
+        let locale = Sorter::new(SortKey::Name, false, GroupOrder::None).locale_aware(true);
+        assert_eq!(
+            sort_with(&names, locale),
+            vec!["Ærø", "Apple", "Ébène", "éclair", "eclipse", "zebra", "Zoë"]
+        );
+
+        // Decomposed accents and ties with the bare letter
+        let locale = Sorter::new(SortKey::Name, false, GroupOrder::None).locale_aware(true);
+        assert_eq!(
+            sort_with(&["ecole", "e\u{301}cole", "ecolf", "école"], locale),
+            vec!["ecole", "e\u{301}cole", "école", "ecolf"]
+        );
+    }
+
+    #[test]
+    fn test_parse_extension_key() {
+        assert!(matches!(SortKey::from_str("ext"), SortKey::Extension));
+        assert!(matches!(SortKey::from_str("extension"), SortKey::Extension));
+    }
+}
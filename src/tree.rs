@@ -1,10 +1,21 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::fs::{self, Metadata};
-use std::os::unix::fs::PermissionsExt;
+use std::io::Read;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::filter::Filter;
-use crate::sort::{SortKey, Sorter};
+use crate::gitignore::{self, Gitignore};
+use crate::sort::{extension, GroupOrder, SortKey, Sorter};
 
 /// Represents a single entry in the directory tree
 #[derive(Debug, Clone)]
@@ -17,6 +28,18 @@ pub struct TreeEntry {
     pub metadata: Option<Metadata>,
     pub children: Vec<TreeEntry>,
     pub error: Option<String>,
+    /// Why a directory is listed without its contents when that isn't a
+    /// failure, e.g. a symlink loop
+    pub note: Option<String>,
+    /// A directory listed without reading it: at the depth limit, a symlink
+    /// that isn't followed, or a mount point with `-x`
+    pub unread: bool,
+    /// Cumulative size of everything beneath a directory (set by `--du`)
+    pub aggregate_size: Option<u64>,
+    /// Number of children left out by `--max-entries`
+    pub omitted: usize,
+    /// Levels below the root of the walk, which has depth 0
+    pub depth: usize,
 }
 
 impl TreeEntry {
@@ -30,7 +53,6 @@ impl TreeEntry {
         let is_symlink = symlink_meta.as_ref().map(|m| m.is_symlink()).unwrap_or(false);
 
         let metadata = fs::metadata(&path).ok();
-        let is_dir = metadata.as_ref().map(|m| m.is_dir()).unwrap_or(false);
 
         let symlink_target = if is_symlink {
             fs::read_link(&path).ok()
@@ -38,6 +60,38 @@ impl TreeEntry {
             None
         };
 
+        Self::from_parts(path, name, is_symlink, symlink_target, metadata)
+    }
+
+    /// Build an entry from a `read_dir` listing with a single stat
+    ///
+    /// The listing already says whether the entry is a symlink, so only the
+    /// target's metadata needs fetching.
+    fn from_dir_entry(dir_entry: &fs::DirEntry) -> Self {
+        let path = dir_entry.path();
+        let name = dir_entry.file_name().to_string_lossy().to_string();
+        let is_symlink = dir_entry.file_type().is_ok_and(|t| t.is_symlink());
+
+        let (metadata, symlink_target) = if is_symlink {
+            (fs::metadata(&path).ok(), fs::read_link(&path).ok())
+        } else {
+            (dir_entry.metadata().ok(), None)
+        };
+
+        Self::from_parts(path, name, is_symlink, symlink_target, metadata)
+    }
+
+    /// Assemble an entry from what `new` looks up, for walkers that do the
+    /// lookups themselves
+    pub(crate) fn from_parts(
+        path: PathBuf,
+        name: String,
+        is_symlink: bool,
+        symlink_target: Option<PathBuf>,
+        metadata: Option<Metadata>,
+    ) -> Self {
+        let is_dir = metadata.as_ref().map(|m| m.is_dir()).unwrap_or(false);
+
         Self {
             path,
             name,
@@ -47,6 +101,11 @@ impl TreeEntry {
             metadata,
             children: Vec::new(),
             error: None,
+            note: None,
+            unread: false,
+            aggregate_size: None,
+            omitted: 0,
+            depth: 0,
         }
     }
 
@@ -55,11 +114,91 @@ impl TreeEntry {
         self.metadata.as_ref().map(|m| m.len()).unwrap_or(0)
     }
 
+    /// Get the size to display: the aggregate size for directories when
+    /// computed, otherwise the entry's own size
+    pub fn display_size(&self) -> u64 {
+        self.aggregate_size.unwrap_or_else(|| self.size())
+    }
+
+    /// Iterate over this entry and all of its descendants in pre-order
+    ///
+    /// Each item is `(depth, entry)`, where this entry has depth 0 and its
+    /// children depth 1. A directory is yielded before its children, and
+    /// siblings are yielded in their current (sorted) order.
+    pub fn iter(&self) -> TreeIter<'_> {
+        self.iter_in(TraversalOrder::DepthFirst)
+    }
+
+    /// Iterate like [`iter`](Self::iter), or level by level with
+    /// [`TraversalOrder::BreadthFirst`]: every entry at depth 1, then every
+    /// entry at depth 2, and so on, keeping each directory's sorted order
+    pub fn iter_in(&self, order: TraversalOrder) -> TreeIter<'_> {
+        TreeIter {
+            pending: VecDeque::from([(0, self)]),
+            order,
+        }
+    }
+
     /// Get modification time
     pub fn modified(&self) -> Option<SystemTime> {
         self.metadata.as_ref().and_then(|m| m.modified().ok())
     }
 
+    /// Get the inode change time (ctime), which also moves on permission
+    /// changes and renames
+    pub fn ctime(&self) -> Option<SystemTime> {
+        let meta = self.metadata.as_ref()?;
+        let secs = u64::try_from(meta.ctime()).ok()?;
+        let nanos = u32::try_from(meta.ctime_nsec()).ok()?;
+        SystemTime::UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+    }
+
+    /// Get the size of a file, or of everything beneath a directory, counting
+    /// files with several hard links in the subtree once
+    ///
+    /// Unlike [`aggregate_size`](Self::aggregate_size), this is worked out on
+    /// each call and stored nowhere.
+    pub fn total_size(&self) -> u64 {
+        fn sum(entry: &TreeEntry, seen_links: &mut HashSet<(u64, u64)>) -> u64 {
+            if !entry.is_dir {
+                return if first_link(entry, seen_links) {
+                    entry.display_size()
+                } else {
+                    0
+                };
+            }
+            entry
+                .children
+                .iter()
+                .map(|child| sum(child, seen_links))
+                .sum()
+        }
+        sum(self, &mut HashSet::new())
+    }
+
+    /// Get the disk space allocated to the entry, in bytes, as `du` reports
+    /// it; entries without metadata fall back to their display size
+    pub fn blocks_size(&self) -> u64 {
+        self.metadata
+            .as_ref()
+            .map_or_else(|| self.display_size(), |m| m.blocks() * 512)
+    }
+
+    /// Get the inode number
+    pub fn inode(&self) -> Option<u64> {
+        self.metadata.as_ref().map(|m| m.ino())
+    }
+
+    /// Get the owning user id
+    pub fn uid(&self) -> Option<u32> {
+        self.metadata.as_ref().map(|m| m.uid())
+    }
+
+    /// Get the owning group id
+    pub fn gid(&self) -> Option<u32> {
+        self.metadata.as_ref().map(|m| m.gid())
+    }
+
     /// Get file permissions as a string (e.g., "drwxr-xr-x")
     pub fn permissions_string(&self) -> String {
         let meta = match &self.metadata {
@@ -72,6 +211,14 @@ impl TreeEntry {
             'd'
         } else if self.is_symlink {
             'l'
+        } else if self.is_fifo() {
+            'p'
+        } else if self.is_socket() {
+            's'
+        } else if self.is_block_device() {
+            'b'
+        } else if self.is_char_device() {
+            'c'
         } else {
             '-'
         };
@@ -94,12 +241,51 @@ impl TreeEntry {
             .unwrap_or(false)
     }
 
+    /// Check if this is a named pipe (FIFO)
+    pub fn is_fifo(&self) -> bool {
+        self.file_type().is_some_and(|t| t.is_fifo())
+    }
+
+    /// Check if this is a Unix domain socket
+    pub fn is_socket(&self) -> bool {
+        self.file_type().is_some_and(|t| t.is_socket())
+    }
+
+    /// Check if this is a block device
+    pub fn is_block_device(&self) -> bool {
+        self.file_type().is_some_and(|t| t.is_block_device())
+    }
+
+    /// Check if this is a character device
+    pub fn is_char_device(&self) -> bool {
+        self.file_type().is_some_and(|t| t.is_char_device())
+    }
+
+    fn file_type(&self) -> Option<fs::FileType> {
+        self.metadata.as_ref().map(Metadata::file_type)
+    }
+
+    /// Check if this is a symlink whose target doesn't exist
+    ///
+    /// Entries read back from a snapshot carry no metadata, so the link is
+    /// checked on disk rather than trusting a missing `metadata`.
+    pub fn is_broken_symlink(&self) -> bool {
+        self.is_symlink
+            && self.metadata.is_none()
+            && fs::symlink_metadata(&self.path).is_ok()
+            && !self.path.exists()
+    }
+
     /// Get the type indicator character (like ls -F)
     pub fn type_indicator(&self) -> &'static str {
         if self.is_dir {
             "/"
         } else if self.is_symlink {
             "@"
+        } else if self.is_socket() {
+            "="
+        } else if self.is_fifo() {
+            "|"
         } else if self.is_executable() {
             "*"
         } else {
@@ -108,6 +294,48 @@ impl TreeEntry {
     }
 }
 
+/// Order in which a tree's entries are visited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TraversalOrder {
+    /// Each directory followed by its contents (pre-order), as in tree output
+    #[default]
+    DepthFirst,
+    /// All entries of one level before any of the next
+    BreadthFirst,
+}
+
+/// Iterator over a tree, created by [`TreeEntry::iter`] and
+/// [`TreeEntry::iter_in`]
+pub struct TreeIter<'a> {
+    /// Used as a stack for depth-first order and a queue for breadth-first
+    pending: VecDeque<(usize, &'a TreeEntry)>,
+    order: TraversalOrder,
+}
+
+impl<'a> Iterator for TreeIter<'a> {
+    type Item = (usize, &'a TreeEntry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let children = |depth: usize, entry: &'a TreeEntry| {
+            entry.children.iter().map(move |child| (depth + 1, child))
+        };
+        match self.order {
+            TraversalOrder::DepthFirst => {
+                let (depth, entry) = self.pending.pop_back()?;
+                // Push in reverse so the first child is visited next
+                self.pending.extend(children(depth, entry).rev());
+                Some((depth, entry))
+            }
+            TraversalOrder::BreadthFirst => {
+                let (depth, entry) = self.pending.pop_front()?;
+                self.pending.extend(children(depth, entry));
+                Some((depth, entry))
+            }
+        }
+    }
+}
+
 fn triplet(mode: u32, special: bool, special_char: char) -> String {
     let r = if mode & 0o4 != 0 { 'r' } else { '-' };
     let w = if mode & 0o2 != 0 { 'w' } else { '-' };
@@ -125,18 +353,132 @@ fn triplet(mode: u32, special: bool, special_char: char) -> String {
     format!("{}{}{}", r, w, x)
 }
 
+/// A caller-supplied test deciding whether an entry is listed, for rules the
+/// built-in filters can't express
+///
+/// Directories are tested too; rejecting one skips its whole subtree.
+#[derive(Clone)]
+pub struct EntryPredicate(Arc<dyn Fn(&TreeEntry) -> bool + Send + Sync>);
+
+impl EntryPredicate {
+    pub fn new(predicate: impl Fn(&TreeEntry) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(predicate))
+    }
+
+    pub fn test(&self, entry: &TreeEntry) -> bool {
+        (self.0)(entry)
+    }
+}
+
+impl fmt::Debug for EntryPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EntryPredicate(..)")
+    }
+}
+
+/// A running count of the entries a walk has scanned, reported to a callback
+/// every `every` entries
+///
+/// Clones share the count, so one `Progress` can follow parallel walks.
+#[derive(Clone)]
+pub struct Progress {
+    every: usize,
+    scanned: Arc<AtomicUsize>,
+    callback: Arc<dyn Fn(usize) + Send + Sync>,
+}
+
+impl Progress {
+    pub fn new(every: usize, callback: impl Fn(usize) + Send + Sync + 'static) -> Self {
+        Self {
+            every: every.max(1),
+            scanned: Arc::new(AtomicUsize::new(0)),
+            callback: Arc::new(callback),
+        }
+    }
+
+    /// Entries scanned so far
+    pub fn scanned(&self) -> usize {
+        self.scanned.load(Ordering::Relaxed)
+    }
+
+    fn tick(&self) {
+        let scanned = self.scanned.fetch_add(1, Ordering::Relaxed) + 1;
+        if scanned.is_multiple_of(self.every) {
+            (self.callback)(scanned);
+        }
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("every", &self.every)
+            .field("scanned", &self.scanned())
+            .finish_non_exhaustive()
+    }
+}
+
 /// Configuration for tree traversal
-#[derive(Debug, Clone)]
+///
+/// Prefer building one with [`TreeConfigBuilder`], which keeps working as
+/// fields are added.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TreeConfig {
     pub show_hidden: bool,
     pub dirs_only: bool,
+    /// Maximum depth to descend: `Some(1)` lists only the root's direct
+    /// children, `Some(2)` their children too, and `None` is unlimited
     pub max_depth: Option<usize>,
     pub follow_symlinks: bool,
     pub full_path: bool,
     pub filter: Filter,
     pub sort_key: SortKey,
     pub sort_reverse: bool,
-    pub dirs_first: bool,
+    /// Seed for [`SortKey::Random`]; `None` shuffles differently every run
+    pub sort_seed: Option<u64>,
+    /// Sort names byte-wise instead of ignoring case
+    pub case_sensitive_sort: bool,
+    /// Collate accented letters with their base letter (`--locale-sort`)
+    pub locale_sort: bool,
+    /// List directories before files, files before directories, or neither
+    pub group_order: GroupOrder,
+    pub respect_gitignore: bool,
+    /// Add the patterns in the walk root's [`TREEIGNORE_FILE`] to the
+    /// filter's excludes
+    pub respect_treeignore: bool,
+    /// Skip version control metadata directories (see [`VCS_DIRS`]), even
+    /// with `show_hidden`
+    pub ignore_vcs: bool,
+    /// Number of worker threads for traversal; `None` walks sequentially
+    pub threads: Option<usize>,
+    /// Drop directories that end up with no entries after filtering
+    pub prune_empty: bool,
+    /// Drop directories with no file anywhere beneath them, including ones
+    /// holding only symlinks to directories or left unread
+    pub only_dirs_with_files: bool,
+    /// Don't descend into directories on other filesystems (`-x`)
+    pub stay_on_filesystem: bool,
+    /// Follow the starting path if it is a symlink, but no symlinks below it
+    /// (`ls -H`)
+    pub dereference_args: bool,
+    /// List at most this many children per directory; the rest are still
+    /// counted in the stats but only shown as a "... N more" line
+    pub max_entries: Option<usize>,
+    /// Don't descend directories with more than this many entries (after
+    /// filtering); they're listed with a note instead
+    pub file_limit: Option<usize>,
+    /// Keep the paths of this many of the largest listed files in
+    /// [`TreeStats::largest_files`]; 0 keeps none
+    pub top_files: usize,
+    /// Extra test applied after the standard filters; library-only, so it's
+    /// never read from or written to config files
+    #[serde(skip)]
+    pub custom_filter: Option<EntryPredicate>,
+    /// Counts every directory entry read, filtered or not; library-only like
+    /// `custom_filter`
+    #[serde(skip)]
+    pub progress: Option<Progress>,
 }
 
 impl Default for TreeConfig {
@@ -150,89 +492,2532 @@ impl Default for TreeConfig {
             filter: Filter::default(),
             sort_key: SortKey::Name,
             sort_reverse: false,
-            dirs_first: false,
+            sort_seed: None,
+            case_sensitive_sort: false,
+            locale_sort: false,
+            group_order: GroupOrder::None,
+            respect_gitignore: false,
+            respect_treeignore: true,
+            ignore_vcs: false,
+            threads: None,
+            prune_empty: false,
+            only_dirs_with_files: false,
+            stay_on_filesystem: false,
+            dereference_args: false,
+            max_entries: None,
+            file_limit: None,
+            top_files: 0,
+            custom_filter: None,
+            progress: None,
+        }
+    }
+}
+
+impl TreeConfig {
+    fn sorter(&self) -> Sorter {
+        let sorter = Sorter::new(self.sort_key.clone(), self.sort_reverse, self.group_order)
+            .case_sensitive(self.case_sensitive_sort)
+            .locale_aware(self.locale_sort);
+        match self.sort_seed {
+            Some(seed) => sorter.seed(seed),
+            None => sorter,
+        }
+    }
+}
+
+/// File of project-local exclude patterns, one per line, read from the walk
+/// root when [`TreeConfig::respect_treeignore`] is set
+pub const TREEIGNORE_FILE: &str = ".treeignore";
+
+/// The contents of `root`'s [`TREEIGNORE_FILE`], if it should be used
+fn read_treeignore(config: &TreeConfig, root: &Path) -> Option<String> {
+    config
+        .respect_treeignore
+        .then(|| fs::read_to_string(root.join(TREEIGNORE_FILE)).ok())
+        .flatten()
+}
+
+/// `config` with the patterns from a [`TREEIGNORE_FILE`] added to its
+/// excludes
+///
+/// A file with an invalid pattern is ignored as a whole, and the returned
+/// message says where the bad pattern is.
+pub(crate) fn with_treeignore<'a>(
+    config: &'a TreeConfig,
+    content: Option<&str>,
+) -> (Cow<'a, TreeConfig>, Option<String>) {
+    let Some(content) = content else {
+        return (Cow::Borrowed(config), None);
+    };
+    let mut extended = config.clone();
+    match extended.filter.add_excludes_from(content) {
+        Ok(()) => (Cow::Owned(extended), None),
+        Err((line, e)) => (
+            Cow::Borrowed(config),
+            Some(format!(
+                "invalid pattern at {}:{}: {}",
+                TREEIGNORE_FILE, line, e
+            )),
+        ),
+    }
+}
+
+/// Directory names skipped by [`TreeConfig::ignore_vcs`]
+pub const VCS_DIRS: &[&str] = &[".git", ".svn", ".hg", ".bzr", "CVS"];
+
+/// Chainable builder for [`TreeConfig`], starting from the defaults
+///
+/// ```
+/// use tree_rust::tree::TreeConfigBuilder;
+///
+/// let config = TreeConfigBuilder::new()
+///     .show_hidden(true)
+///     .max_depth(Some(3))
+///     .build();
+/// assert!(config.show_hidden);
+/// assert_eq!(config.max_depth, Some(3));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TreeConfigBuilder {
+    config: TreeConfig,
+}
+
+impl TreeConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    builder_setters! {
+        show_hidden: bool,
+        dirs_only: bool,
+        max_depth: Option<usize>,
+        follow_symlinks: bool,
+        full_path: bool,
+        filter: Filter,
+        sort_key: SortKey,
+        sort_reverse: bool,
+        sort_seed: Option<u64>,
+        case_sensitive_sort: bool,
+        locale_sort: bool,
+        group_order: GroupOrder,
+        respect_gitignore: bool,
+        respect_treeignore: bool,
+        ignore_vcs: bool,
+        threads: Option<usize>,
+        prune_empty: bool,
+        only_dirs_with_files: bool,
+        stay_on_filesystem: bool,
+        dereference_args: bool,
+        max_entries: Option<usize>,
+        file_limit: Option<usize>,
+        top_files: usize,
+    }
+
+    /// Only list entries for which `predicate` returns true, on top of the
+    /// standard filters
+    pub fn custom_filter(
+        mut self,
+        predicate: impl Fn(&TreeEntry) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.config.custom_filter = Some(EntryPredicate::new(predicate));
+        self
+    }
+
+    /// Call `callback` with the number of entries scanned every `every`
+    /// entries during a walk
+    pub fn progress(
+        mut self,
+        every: usize,
+        callback: impl Fn(usize) + Send + Sync + 'static,
+    ) -> Self {
+        self.config.progress = Some(Progress::new(every, callback));
+        self
+    }
+
+    pub fn build(self) -> TreeConfig {
+        self.config
+    }
+}
+
+/// The largest files seen so far, up to a limit, for `--top`
+///
+/// Only the current top `limit` are held, in a min-heap, so a walk over
+/// millions of files doesn't keep them all. Among files of equal size the
+/// first by path win, so the result doesn't depend on traversal order.
+#[derive(Debug, Clone, Default)]
+pub struct LargestFiles {
+    limit: usize,
+    heap: BinaryHeap<Reverse<(u64, Reverse<PathBuf>)>>,
+}
+
+impl LargestFiles {
+    /// Keep at most `limit` of the largest files offered
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    /// Consider a file, keeping it if it's among the largest so far
+    pub fn offer(&mut self, size: u64, path: &Path) {
+        let candidate = (size, Reverse(path.to_path_buf()));
+        if self.heap.len() < self.limit {
+            self.heap.push(Reverse(candidate));
+        } else if self
+            .heap
+            .peek()
+            .is_some_and(|Reverse(min)| candidate > *min)
+        {
+            self.heap.pop();
+            self.heap.push(Reverse(candidate));
         }
     }
+
+    /// Fold in the files kept by another traversal
+    pub fn merge(&mut self, other: LargestFiles) {
+        self.limit = self.limit.max(other.limit);
+        for Reverse((size, Reverse(path))) in other.heap {
+            self.offer(size, &path);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// The kept files, largest first
+    pub fn sorted(&self) -> Vec<(u64, &Path)> {
+        let mut files: Vec<_> = self.heap.iter().map(|Reverse(file)| file).collect();
+        files.sort_by(|a, b| b.cmp(a));
+        files
+            .into_iter()
+            .map(|(size, Reverse(path))| (*size, path.as_path()))
+            .collect()
+    }
 }
 
 /// Statistics collected during tree traversal
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct TreeStats {
     pub directories: usize,
     pub files: usize,
+    /// Sum of the sizes of all listed files, in bytes
+    pub total_size: u64,
+    /// File counts keyed by lowercased extension; `""` holds files without one
+    pub extensions: HashMap<String, usize>,
+    /// Directories that couldn't be read
+    pub errors: usize,
+    /// Lines across all listed text files (set by `--wc`)
+    pub total_lines: u64,
+    /// Depth of the deepest listed entry, the root's children being depth 1
+    pub max_depth_reached: usize,
+    /// Directories whose contents were read, including the root
+    pub listed_dirs: usize,
+    /// Entries listed across those directories
+    pub listed_children: usize,
+    /// The biggest files listed, when [`TreeConfig::top_files`] is set
+    pub largest_files: LargestFiles,
+    /// [`TreeConfig::max_depth`] the tree was walked with
+    pub depth_limit: Option<usize>,
+}
+
+impl TreeStats {
+    /// Count everything below `entry`, e.g. for a tree that wasn't walked
+    pub fn from_tree(entry: &TreeEntry) -> Self {
+        let mut stats = Self::default();
+        for (_, child) in entry.iter().filter(|(depth, _)| *depth > 0) {
+            if child.is_dir {
+                stats.directories += 1;
+            } else {
+                stats.add_file(child);
+            }
+        }
+        stats.errors = entry.iter().filter(|(_, e)| e.error.is_some()).count();
+        for (depth, dir) in entry.iter().filter(|(_, e)| e.is_dir) {
+            stats.add_listing(depth, dir.children.len());
+        }
+        stats
+    }
+
+    /// Record a directory at `depth` read with `children` entries
+    fn add_listing(&mut self, depth: usize, children: usize) {
+        self.listed_dirs += 1;
+        self.listed_children += children;
+        if children > 0 {
+            self.max_depth_reached = self.max_depth_reached.max(depth + 1);
+        }
+    }
+
+    /// Mean number of entries per directory read, or 0 if none were
+    pub fn average_branching(&self) -> f64 {
+        if self.listed_dirs == 0 {
+            0.0
+        } else {
+            self.listed_children as f64 / self.listed_dirs as f64
+        }
+    }
+
+    /// Count a listed file
+    pub fn add_file(&mut self, entry: &TreeEntry) {
+        self.files += 1;
+        self.total_size += entry.display_size();
+        let ext = extension(&entry.name).unwrap_or_default();
+        *self.extensions.entry(ext).or_insert(0) += 1;
+    }
+
+    /// Count the lines of every regular file listed below `entry` into
+    /// `total_lines`, like `wc -l`
+    ///
+    /// Binary files, detected by a NUL byte, and unreadable files are skipped.
+    pub fn count_lines(&mut self, entry: &TreeEntry) {
+        self.total_lines += entry
+            .iter()
+            .filter(|(depth, e)| *depth > 0 && e.metadata.as_ref().is_some_and(|m| m.is_file()))
+            .filter_map(|(_, e)| line_count(&e.path))
+            .sum::<u64>();
+    }
+
+    /// Add the counts from another traversal into this one
+    pub fn merge(&mut self, other: TreeStats) {
+        self.directories += other.directories;
+        self.files += other.files;
+        self.total_size += other.total_size;
+        self.errors += other.errors;
+        self.total_lines += other.total_lines;
+        self.max_depth_reached = self.max_depth_reached.max(other.max_depth_reached);
+        self.listed_dirs += other.listed_dirs;
+        self.listed_children += other.listed_children;
+        self.largest_files.merge(other.largest_files);
+        for (ext, count) in other.extensions {
+            *self.extensions.entry(ext).or_insert(0) += count;
+        }
+    }
+}
+
+/// Newlines in a file, or `None` if it's binary or can't be read
+fn line_count(path: &Path) -> Option<u64> {
+    let mut file = fs::File::open(path).ok()?;
+    let mut buf = [0u8; 64 * 1024];
+    let mut lines = 0;
+    loop {
+        let n = file.read(&mut buf).ok()?;
+        if n == 0 {
+            return Some(lines);
+        }
+        let chunk = &buf[..n];
+        if chunk.contains(&0) {
+            return None;
+        }
+        lines += chunk.iter().filter(|&&b| b == b'\n').count() as u64;
+    }
 }
 
 /// Walk a directory and build a tree structure
+///
+/// An invalid pattern in the root's [`TREEIGNORE_FILE`] is reported as an
+/// error on the root entry.
 pub fn walk_directory(
     path: &Path,
     config: &TreeConfig,
     stats: &mut TreeStats,
     current_depth: usize,
 ) -> TreeEntry {
-    let mut entry = TreeEntry::new(path.to_path_buf());
+    let treeignore = read_treeignore(config, path);
+    let (config, treeignore_error) = with_treeignore(config, treeignore.as_deref());
+    let config = &*config;
+    stats.depth_limit = config.max_depth;
+    stats.largest_files = LargestFiles::new(config.top_files);
+    let visited = HashSet::new();
+    let ancestry = Ancestry {
+        ignores: &[],
+        visited: &visited,
+        root_dev: config
+            .stay_on_filesystem
+            .then(|| fs::metadata(path).ok().map(|m| m.dev()))
+            .flatten(),
+        top_level: true,
+        root: path,
+        include_all: false,
+    };
+
+    let root = TreeEntry::new(path.to_path_buf());
+    let pool = config
+        .threads
+        .map(|n| rayon::ThreadPoolBuilder::new().num_threads(n).build());
+
+    let mut tree = match pool {
+        Some(Ok(pool)) => pool.install(|| walk(root, config, stats, current_depth, ancestry)),
+        _ => walk(root, config, stats, current_depth, ancestry),
+    };
+    if let Some(error) = treeignore_error {
+        tree.error.get_or_insert(error);
+        stats.errors += 1;
+    }
+    tree
+}
+
+/// State handed down from ancestor directories during a walk
+#[derive(Clone, Copy)]
+struct Ancestry<'a> {
+    /// `.gitignore` rules in effect, innermost last
+    ignores: &'a [Gitignore],
+    /// Canonical paths of ancestor directories, tracked when following symlinks
+    visited: &'a HashSet<PathBuf>,
+    /// Device of the root directory when staying on one filesystem
+    root_dev: Option<u64>,
+    /// Whether this is the path the walk started from
+    top_level: bool,
+    /// The path the walk started from, for root-relative pattern matching
+    root: &'a Path,
+    /// Inside a directory matched under `match_dirs`
+    include_all: bool,
+}
+
+/// Whether a device id differs from the root's, i.e. it's on another filesystem
+pub(crate) fn crosses_filesystem(root_dev: Option<u64>, dev: Option<u64>) -> bool {
+    matches!((root_dev, dev), (Some(root), Some(dev)) if root != dev)
+}
+
+fn walk(
+    mut entry: TreeEntry,
+    config: &TreeConfig,
+    stats: &mut TreeStats,
+    current_depth: usize,
+    ancestry: Ancestry<'_>,
+) -> TreeEntry {
+    entry.depth = current_depth;
+
+    if !entry.is_dir {
+        return entry;
+    }
 
-    // Check depth limit
+    // Check depth limit; entries at `max_depth` are listed but not descended
     if let Some(max_depth) = config.max_depth {
         if current_depth >= max_depth {
+            entry.unread = true;
             return entry;
         }
     }
 
-    if !entry.is_dir {
+    let follow = config.follow_symlinks || (config.dereference_args && ancestry.top_level);
+    if entry.is_symlink && !follow {
+        entry.unread = true;
+        return entry;
+    }
+
+    // Mount points are listed but not descended with -x
+    let dev = entry.metadata.as_ref().map(|m| m.dev());
+    if crosses_filesystem(ancestry.root_dev, dev) {
+        entry.unread = true;
         return entry;
     }
 
+    // When following symlinks, track the canonical path of every directory on
+    // the way down so a link back to an ancestor isn't walked forever
+    let nested_visited: HashSet<PathBuf>;
+    let mut ancestry = ancestry;
+    ancestry.top_level = false;
+    if config.follow_symlinks {
+        let canonical = fs::canonicalize(&entry.path).unwrap_or_else(|_| entry.path.clone());
+        if ancestry.visited.contains(&canonical) {
+            entry.note = Some("recursive, not followed".to_string());
+            return entry;
+        }
+        nested_visited = ancestry
+            .visited
+            .iter()
+            .cloned()
+            .chain([canonical])
+            .collect();
+        ancestry.visited = &nested_visited;
+    }
+
     // Read directory contents
-    let read_dir = match fs::read_dir(path) {
+    let read_dir = match open_dir(&entry.path) {
         Ok(rd) => rd,
         Err(e) => {
             entry.error = Some(format!("error opening dir: {}", e));
+            stats.errors += 1;
             return entry;
         }
     };
 
-    let mut children: Vec<TreeEntry> = Vec::new();
+    // Rules from this directory's .gitignore apply to its whole subtree
+    let nested_ignores: Vec<Gitignore>;
+    if config.respect_gitignore {
+        if let Some(gitignore) = Gitignore::from_dir(&entry.path) {
+            nested_ignores = [ancestry.ignores, &[gitignore]].concat();
+            ancestry.ignores = &nested_ignores;
+        }
+    }
+
+    // Filtering happens before recursing, so an excluded directory is never
+    // opened at all
+    let child_entries = filtered_children(
+        read_dir,
+        config,
+        ancestry.root,
+        ancestry.ignores,
+        ancestry.include_all,
+    );
+    if let Some(note) = file_limit_note(config, child_entries.len()) {
+        entry.note = Some(note);
+        return entry;
+    }
+
+    let child_ancestry = |child_path: &Path| Ancestry {
+        include_all: ancestry.include_all || includes_subtree(config, ancestry.root, child_path),
+        ..ancestry
+    };
+
+    // Recursively walk subdirectories, fanning out across the pool if enabled
+    let children: Vec<TreeEntry> = if config.threads.is_some() {
+        child_entries
+            .into_par_iter()
+            .map(|child| {
+                let mut child_stats = TreeStats {
+                    largest_files: LargestFiles::new(config.top_files),
+                    ..TreeStats::default()
+                };
+                let ancestry = child_ancestry(&child.path);
+                let child = walk(child, config, &mut child_stats, current_depth + 1, ancestry);
+                (child, child_stats)
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|(child, child_stats)| {
+                stats.merge(child_stats);
+                child
+            })
+            .collect()
+    } else {
+        child_entries
+            .into_iter()
+            .map(|child| {
+                let ancestry = child_ancestry(&child.path);
+                walk(child, config, stats, current_depth + 1, ancestry)
+            })
+            .collect()
+    };
 
-    for dir_entry in read_dir.flatten() {
-        let child_path = dir_entry.path();
-        let child_name = child_path
-            .file_name()
-            .map(|s| s.to_string_lossy().to_string())
-            .unwrap_or_default();
+    finish_directory(&mut entry, children, config, stats, current_depth);
+    entry
+}
 
-        // Skip hidden files unless -a is specified
-        if !config.show_hidden && child_name.starts_with('.') {
-            continue;
+/// Prune, count, sort and truncate a directory's walked children, then attach
+/// them to it
+pub(crate) fn finish_directory(
+    entry: &mut TreeEntry,
+    mut children: Vec<TreeEntry>,
+    config: &TreeConfig,
+    stats: &mut TreeStats,
+    current_depth: usize,
+) {
+    // Prune directories left empty by filtering; children were pruned first,
+    // so chains of empty directories collapse. Directories cut off by the
+    // depth limit were never read and are kept.
+    let at_depth_limit = config.max_depth.is_some_and(|max| current_depth + 1 >= max);
+    if config.prune_empty && !at_depth_limit {
+        children.retain(|child| {
+            !child.is_dir
+                || child.is_symlink
+                || child.error.is_some()
+                || child.note.is_some()
+                || !child.children.is_empty()
+        });
+    }
+
+    // Stricter: keep only directories with a file somewhere below them. As
+    // with pruning, children were filtered first, so any child left inside a
+    // directory means a file beneath it; unread directories don't count.
+    if config.only_dirs_with_files {
+        children.retain(|child| !child.is_dir || !child.children.is_empty());
+    }
+
+    for child in &children {
+        if child.is_dir {
+            stats.directories += 1;
+        } else {
+            stats.add_file(child);
+            if config.top_files > 0 && !child.is_symlink {
+                stats.largest_files.offer(child.display_size(), &child.path);
+            }
         }
+    }
+    stats.add_listing(current_depth, children.len());
+
+    // Sort children
+    config.sorter().sort(&mut children);
+
+    if let Some(max) = config.max_entries {
+        if children.len() > max {
+            entry.omitted = children.len() - max;
+            children.truncate(max);
+        }
+    }
+
+    entry.children = children;
+}
+
+/// Open a directory for listing
+///
+/// Tests record every directory opened, to check which ones a walk reads.
+fn open_dir(path: &Path) -> std::io::Result<fs::ReadDir> {
+    #[cfg(test)]
+    tests::DIRS_OPENED.with(|opened| opened.borrow_mut().push(path.to_path_buf()));
+    fs::read_dir(path)
+}
+
+/// Entries in a directory that pass the hidden, dirs-only, pattern,
+/// gitignore, metadata and custom filters
+///
+/// Each entry is looked up once, and the same metadata serves the filters
+/// and the returned entry. With `include_all`, set inside a directory matched
+/// under `match_dirs`, include patterns are skipped.
+fn filtered_children(
+    read_dir: fs::ReadDir,
+    config: &TreeConfig,
+    root: &Path,
+    ignores: &[Gitignore],
+    include_all: bool,
+) -> Vec<TreeEntry> {
+    let mut children = Vec::new();
 
-        let child_is_dir = child_path.is_dir();
+    for dir_entry in read_dir.flatten() {
+        if let Some(ref progress) = config.progress {
+            progress.tick();
+        }
 
-        // Skip files if dirs_only
-        if config.dirs_only && !child_is_dir {
+        // Hidden names are skipped before paying for a stat
+        if !config.show_hidden && dir_entry.file_name().as_encoded_bytes().starts_with(b".") {
             continue;
         }
 
-        // Apply filters
-        if !config.filter.matches(&child_name, child_is_dir) {
+        let child = TreeEntry::from_dir_entry(&dir_entry);
+        if !passes_filters(
+            config,
+            root,
+            ignores,
+            include_all,
+            &child.path,
+            &child.name,
+            child.is_dir,
+        ) {
             continue;
         }
 
-        // Recursively walk subdirectories
-        let child = walk_directory(&child_path, config, stats, current_depth + 1);
+        if !passes_metadata_filters(config, child.metadata.as_ref()) {
+            continue;
+        }
 
-        if child.is_dir {
-            stats.directories += 1;
-        } else {
-            stats.files += 1;
+        if let Some(ref predicate) = config.custom_filter {
+            if !predicate.test(&child) {
+                continue;
+            }
         }
 
         children.push(child);
     }
 
-    // Sort children
-    let sorter = Sorter::new(config.sort_key.clone(), config.sort_reverse, config.dirs_first);
-    sorter.sort(&mut children);
+    children
+}
 
-    entry.children = children;
-    entry
+/// Whether an entry passes the size, time and permission filters, if any are
+/// set; an entry without metadata, like a broken symlink, has nothing to
+/// test and fails them
+pub(crate) fn passes_metadata_filters(config: &TreeConfig, metadata: Option<&Metadata>) -> bool {
+    !config.filter.needs_metadata() || metadata.is_some_and(|m| config.filter.matches_metadata(m))
+}
+
+/// Whether a directory entry passes the hidden, dirs-only, pattern and
+/// gitignore filters; metadata filters are checked separately
+pub(crate) fn passes_filters(
+    config: &TreeConfig,
+    root: &Path,
+    ignores: &[Gitignore],
+    include_all: bool,
+    path: &Path,
+    name: &str,
+    is_dir: bool,
+) -> bool {
+    // Skip hidden files unless -a is specified
+    if !config.show_hidden && name.starts_with('.') {
+        return false;
+    }
+
+    if config.ignore_vcs && is_dir && VCS_DIRS.contains(&name) {
+        return false;
+    }
+
+    // Skip files if dirs_only
+    if config.dirs_only && !is_dir {
+        return false;
+    }
+
+    // Apply filters
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let matches = if include_all {
+        !config.filter.is_excluded(name, relative)
+    } else {
+        config.filter.matches(name, relative, is_dir)
+    };
+    if !matches {
+        return false;
+    }
+
+    !(config.respect_gitignore && gitignore::is_ignored(ignores, path, is_dir))
+}
+
+/// The note for a directory with more entries than `file_limit`, which is
+/// listed but not opened
+pub(crate) fn file_limit_note(config: &TreeConfig, count: usize) -> Option<String> {
+    config
+        .file_limit
+        .filter(|&limit| count > limit)
+        .map(|_| format!("{} entries exceeds filelimit, not opening dir", count))
+}
+
+/// Whether a directory matches an include pattern under `match_dirs`, which
+/// lists everything beneath it
+pub(crate) fn includes_subtree(config: &TreeConfig, root: &Path, dir: &Path) -> bool {
+    let name = dir
+        .file_name()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    let relative = dir.strip_prefix(root).unwrap_or(dir);
+    config.filter.includes_dir(&name, relative)
+}
+
+/// Collect the files under `root` that pass the filters in `config`, without
+/// building a tree
+///
+/// Directories are only descended, never returned, and filtered-out branches
+/// are skipped without reading their metadata. Paths come back sorted. A
+/// [`TREEIGNORE_FILE`] with an invalid pattern is skipped.
+pub fn find_matching(root: &Path, config: &TreeConfig) -> Vec<PathBuf> {
+    let treeignore = read_treeignore(config, root);
+    let (config, _) = with_treeignore(config, treeignore.as_deref());
+    let config = &*config;
+    let mut found = Vec::new();
+    let mut ancestors = Vec::new();
+    find_in(
+        root,
+        root,
+        config,
+        0,
+        &[],
+        false,
+        &mut ancestors,
+        &mut found,
+    );
+    found.sort();
+    found
+}
+
+#[allow(clippy::too_many_arguments)]
+fn find_in(
+    root: &Path,
+    path: &Path,
+    config: &TreeConfig,
+    current_depth: usize,
+    ignores: &[Gitignore],
+    include_all: bool,
+    ancestors: &mut Vec<PathBuf>,
+    found: &mut Vec<PathBuf>,
+) {
+    if config.max_depth.is_some_and(|max| current_depth >= max) {
+        return;
+    }
+
+    // Guard against symlink loops the same way `walk` does
+    let canonical = config
+        .follow_symlinks
+        .then(|| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+    if let Some(ref canonical) = canonical {
+        if ancestors.contains(canonical) {
+            return;
+        }
+    }
+
+    let Ok(read_dir) = open_dir(path) else {
+        return;
+    };
+
+    let nested_ignores: Vec<Gitignore>;
+    let mut ignores = ignores;
+    if config.respect_gitignore {
+        if let Some(gitignore) = Gitignore::from_dir(path) {
+            nested_ignores = [ignores, &[gitignore]].concat();
+            ignores = &nested_ignores;
+        }
+    }
+
+    if let Some(canonical) = canonical.clone() {
+        ancestors.push(canonical);
+    }
+    for child in filtered_children(read_dir, config, root, ignores, include_all) {
+        if !child.is_dir {
+            found.push(child.path);
+        } else if config.follow_symlinks || !child.is_symlink {
+            find_in(
+                root,
+                &child.path,
+                config,
+                current_depth + 1,
+                ignores,
+                include_all || includes_subtree(config, root, &child.path),
+                ancestors,
+                found,
+            );
+        }
+    }
+    if canonical.is_some() {
+        ancestors.pop();
+    }
+}
+
+/// Walk a directory like [`walk_directory`], handing each entry to `callback`
+/// with its depth as it's discovered instead of building the whole tree
+///
+/// Entries arrive in depth-first pre-order with their `children` left empty;
+/// only one directory's listing is buffered at a time, for sorting and
+/// `max_entries`. Options that need a finished subtree (`prune_empty`,
+/// `only_dirs_with_files` and sorting by total size) can't be applied, and
+/// `threads` is ignored.
+pub fn walk_streaming<F: FnMut(&TreeEntry, usize)>(
+    path: &Path,
+    config: &TreeConfig,
+    mut callback: F,
+) {
+    let treeignore = read_treeignore(config, path);
+    let (config, treeignore_error) = with_treeignore(config, treeignore.as_deref());
+    let config = &*config;
+    let mut root = TreeEntry::new(path.to_path_buf());
+    root.error = treeignore_error;
+    let root_dev = config
+        .stay_on_filesystem
+        .then(|| fs::metadata(path).ok().map(|m| m.dev()))
+        .flatten();
+    let mut ancestors = Vec::new();
+    stream_in(
+        root,
+        path,
+        root_dev,
+        config,
+        0,
+        &[],
+        false,
+        &mut ancestors,
+        &mut callback,
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
+fn stream_in<F: FnMut(&TreeEntry, usize)>(
+    mut entry: TreeEntry,
+    root: &Path,
+    root_dev: Option<u64>,
+    config: &TreeConfig,
+    current_depth: usize,
+    ignores: &[Gitignore],
+    include_all: bool,
+    ancestors: &mut Vec<PathBuf>,
+    callback: &mut F,
+) {
+    entry.depth = current_depth;
+
+    // Same descent rules as `walk`: depth limit, symlinks, mount points
+    let follow = config.follow_symlinks || (config.dereference_args && current_depth == 0);
+    let dev = entry.metadata.as_ref().map(|m| m.dev());
+    let descend = entry.is_dir
+        && (follow || !entry.is_symlink)
+        && config.max_depth.is_none_or(|max| current_depth < max)
+        && !crosses_filesystem(root_dev, dev);
+    if !descend {
+        entry.unread = entry.is_dir;
+        callback(&entry, current_depth);
+        return;
+    }
+
+    let canonical = config
+        .follow_symlinks
+        .then(|| fs::canonicalize(&entry.path).unwrap_or_else(|_| entry.path.clone()));
+    if let Some(ref canonical) = canonical {
+        if ancestors.contains(canonical) {
+            entry.note = Some("recursive, not followed".to_string());
+            callback(&entry, current_depth);
+            return;
+        }
+    }
+
+    let read_dir = match open_dir(&entry.path) {
+        Ok(rd) => rd,
+        Err(e) => {
+            entry.error = Some(format!("error opening dir: {}", e));
+            callback(&entry, current_depth);
+            return;
+        }
+    };
+
+    let nested_ignores: Vec<Gitignore>;
+    let mut ignores = ignores;
+    if config.respect_gitignore {
+        if let Some(gitignore) = Gitignore::from_dir(&entry.path) {
+            nested_ignores = [ignores, &[gitignore]].concat();
+            ignores = &nested_ignores;
+        }
+    }
+
+    // The only buffered listing: this directory's own children, unexpanded
+    let mut children = filtered_children(read_dir, config, root, ignores, include_all);
+    if let Some(note) = file_limit_note(config, children.len()) {
+        entry.note = Some(note);
+        callback(&entry, current_depth);
+        return;
+    }
+    config.sorter().sort(&mut children);
+    if let Some(max) = config.max_entries {
+        if children.len() > max {
+            entry.omitted = children.len() - max;
+            children.truncate(max);
+        }
+    }
+
+    callback(&entry, current_depth);
+    drop(entry);
+
+    if let Some(canonical) = canonical.clone() {
+        ancestors.push(canonical);
+    }
+    for child in children {
+        let child_include_all =
+            include_all || (child.is_dir && includes_subtree(config, root, &child.path));
+        stream_in(
+            child,
+            root,
+            root_dev,
+            config,
+            current_depth + 1,
+            ignores,
+            child_include_all,
+            ancestors,
+            callback,
+        );
+    }
+    if canonical.is_some() {
+        ancestors.pop();
+    }
+}
+
+/// Build a tree from an explicit list of paths instead of walking the
+/// filesystem, e.g. the output of `find`
+///
+/// The first path is the root and every other path must lie beneath it;
+/// missing intermediate directories are filled in. Paths that don't exist or
+/// fall outside the root are skipped and reported in the returned errors.
+/// The filters in `config` apply as in a walk: a path is dropped when it, or
+/// any directory on the way to it, is filtered out.
+pub fn tree_from_paths(
+    paths: &[PathBuf],
+    config: &TreeConfig,
+    stats: &mut TreeStats,
+) -> (Option<TreeEntry>, Vec<String>) {
+    stats.depth_limit = config.max_depth;
+    stats.largest_files = LargestFiles::new(config.top_files);
+    let mut errors = Vec::new();
+    let Some((root_path, rest)) = paths.split_first() else {
+        return (None, errors);
+    };
+    if fs::symlink_metadata(root_path).is_err() {
+        errors.push(format!(
+            "{}: no such file or directory",
+            root_path.display()
+        ));
+        return (None, errors);
+    }
+    let treeignore = read_treeignore(config, root_path);
+    let (config, treeignore_error) = with_treeignore(config, treeignore.as_deref());
+    let config = &*config;
+    errors.extend(treeignore_error);
+
+    // Entries below the root by path, and each directory's children in input
+    // order; the tree is assembled from these once every path is placed
+    let mut nodes: HashMap<PathBuf, TreeEntry> = HashMap::new();
+    let mut children: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    // The .gitignore rules in effect inside each placed directory
+    let mut ignores: HashMap<PathBuf, Vec<Gitignore>> = HashMap::new();
+    ignores.insert(root_path.clone(), gitignore_stack(config, &[], root_path));
+
+    'paths: for path in rest {
+        let Ok(relative) = path.strip_prefix(root_path) else {
+            errors.push(format!(
+                "{}: not under {}",
+                path.display(),
+                root_path.display()
+            ));
+            continue;
+        };
+        if fs::symlink_metadata(path).is_err() {
+            errors.push(format!("{}: no such file or directory", path.display()));
+            continue;
+        }
+
+        let mut parent = root_path.clone();
+        for component in relative.components() {
+            let node_path = parent.join(component);
+            if !nodes.contains_key(&node_path) {
+                let entry = TreeEntry::new(node_path.clone());
+                let parent_ignores = ignores.get(&parent).map(Vec::as_slice).unwrap_or(&[]);
+                if !keeps_listed_entry(config, root_path, parent_ignores, &entry) {
+                    continue 'paths;
+                }
+                if entry.is_dir {
+                    let stack = gitignore_stack(config, parent_ignores, &node_path);
+                    ignores.insert(node_path.clone(), stack);
+                }
+                children
+                    .entry(parent.clone())
+                    .or_default()
+                    .push(node_path.clone());
+                nodes.insert(node_path.clone(), entry);
+            }
+            parent = node_path;
+        }
+    }
+
+    let mut root = TreeEntry::new(root_path.clone());
+    attach_children(&mut root, &mut nodes, &mut children);
+    sort_and_count(&mut root, &config.sorter(), stats, 0);
+    if config.top_files > 0 {
+        for (_, file) in root
+            .iter()
+            .filter(|(depth, e)| *depth > 0 && !e.is_dir && !e.is_symlink)
+        {
+            stats.largest_files.offer(file.display_size(), &file.path);
+        }
+    }
+
+    (Some(root), errors)
+}
+
+/// Whether a path named on input passes the same filters as a walked entry
+fn keeps_listed_entry(
+    config: &TreeConfig,
+    root: &Path,
+    ignores: &[Gitignore],
+    entry: &TreeEntry,
+) -> bool {
+    passes_filters(
+        config,
+        root,
+        ignores,
+        false,
+        &entry.path,
+        &entry.name,
+        entry.is_dir,
+    ) && passes_metadata_filters(config, entry.metadata.as_ref())
+        && config
+            .custom_filter
+            .as_ref()
+            .is_none_or(|predicate| predicate.test(entry))
+}
+
+/// `parent`'s .gitignore rules plus those of `dir` itself
+fn gitignore_stack(config: &TreeConfig, parent: &[Gitignore], dir: &Path) -> Vec<Gitignore> {
+    let mut stack = parent.to_vec();
+    if config.respect_gitignore {
+        stack.extend(Gitignore::from_dir(dir));
+    }
+    stack
+}
+
+/// Move the entries listed under `entry` in `children` out of `nodes` and
+/// into place, recursively
+fn attach_children(
+    entry: &mut TreeEntry,
+    nodes: &mut HashMap<PathBuf, TreeEntry>,
+    children: &mut HashMap<PathBuf, Vec<PathBuf>>,
+) {
+    for path in children.remove(&entry.path).unwrap_or_default() {
+        if let Some(mut child) = nodes.remove(&path) {
+            attach_children(&mut child, nodes, children);
+            entry.children.push(child);
+        }
+    }
+}
+
+fn sort_and_count(entry: &mut TreeEntry, sorter: &Sorter, stats: &mut TreeStats, depth: usize) {
+    entry.depth = depth;
+    sorter.sort(&mut entry.children);
+    if entry.is_dir {
+        stats.add_listing(depth, entry.children.len());
+    }
+    for child in &mut entry.children {
+        if child.is_dir {
+            stats.directories += 1;
+        } else {
+            stats.add_file(child);
+        }
+        sort_and_count(child, sorter, stats, depth + 1);
+    }
+}
+
+/// Compute cumulative directory sizes bottom-up, like `du`
+///
+/// Each directory's `aggregate_size` becomes the sum of the sizes of all
+/// entries beneath it. Files with several hard links in the tree are only
+/// counted at their first appearance. Returns the size the entry contributes
+/// to its parent.
+pub fn compute_dir_sizes(entry: &mut TreeEntry) -> u64 {
+    aggregate_sizes(entry, &mut HashSet::new(), TreeEntry::display_size)
+}
+
+/// Like [`compute_dir_sizes`], but summing allocated disk space
+/// ([`TreeEntry::blocks_size`]) rather than apparent file sizes
+pub fn compute_dir_block_sizes(entry: &mut TreeEntry) -> u64 {
+    aggregate_sizes(entry, &mut HashSet::new(), TreeEntry::blocks_size)
+}
+
+fn aggregate_sizes(
+    entry: &mut TreeEntry,
+    seen_links: &mut HashSet<(u64, u64)>,
+    file_size: fn(&TreeEntry) -> u64,
+) -> u64 {
+    if !entry.is_dir {
+        return if first_link(entry, seen_links) {
+            file_size(entry)
+        } else {
+            0
+        };
+    }
+
+    let total = entry
+        .children
+        .iter_mut()
+        .map(|child| aggregate_sizes(child, seen_links, file_size))
+        .sum();
+    entry.aggregate_size = Some(total);
+    total
+}
+
+/// Whether a file is seen for the first time, i.e. isn't another hard link
+/// to a file already in `seen_links`
+fn first_link(entry: &TreeEntry, seen_links: &mut HashSet<(u64, u64)>) -> bool {
+    match entry.metadata.as_ref().filter(|m| m.nlink() > 1) {
+        Some(meta) => seen_links.insert((meta.dev(), meta.ino())),
+        None => true,
+    }
+}
+
+/// Merge chains of directories that each hold nothing but one subdirectory
+/// into a single `a/b/c` entry, for `--collapse`
+///
+/// The merged entry takes the innermost directory's path and children but
+/// the outermost one's depth, and everything below it moves up to match. The
+/// root itself keeps its name; symlinks and directories with errors or
+/// omitted entries end a chain.
+pub fn collapse_chains(entry: &mut TreeEntry) {
+    for child in &mut entry.children {
+        let depth = child.depth;
+        while let Some(only) = single_subdir(child) {
+            let name = format!("{}/{}", child.name, only.name);
+            *child = only;
+            child.name = name;
+        }
+        if child.depth != depth {
+            set_depths(child, depth);
+        }
+        collapse_chains(child);
+    }
+}
+
+/// Renumber `entry` as `depth` levels down, and its descendants below it
+fn set_depths(entry: &mut TreeEntry, depth: usize) {
+    entry.depth = depth;
+    for child in &mut entry.children {
+        set_depths(child, depth + 1);
+    }
+}
+
+/// Take the only child of `entry` if it can be merged into it
+fn single_subdir(entry: &mut TreeEntry) -> Option<TreeEntry> {
+    let mergeable = |e: &TreeEntry| e.is_dir && !e.is_symlink && e.error.is_none();
+    let chain = mergeable(entry)
+        && entry.omitted == 0
+        && entry.children.len() == 1
+        && mergeable(&entry.children[0]);
+    chain.then(|| entry.children.remove(0))
+}
+
+/// Keep only the entries exactly `depth` levels below `entry`, for `--at-depth`
+///
+/// The directories leading down to them stay for context, and branches that
+/// never reach `depth` are dropped. With `flatten` the selected entries become
+/// direct children of `entry` instead. Selected directories lose their own
+/// children either way.
+pub fn select_depth(entry: &mut TreeEntry, depth: usize, flatten: bool) {
+    if flatten && depth > 1 {
+        let mut selected = Vec::new();
+        collect_at_depth(
+            std::mem::take(&mut entry.children),
+            depth - 1,
+            &mut selected,
+        );
+        entry.children = selected;
+        entry.omitted = 0;
+    } else {
+        keep_at_depth(entry, depth);
+    }
+}
+
+/// Prune `entry` to the branches reaching `depth`, returning whether any do
+fn keep_at_depth(entry: &mut TreeEntry, depth: usize) -> bool {
+    if depth == 0 {
+        entry.children.clear();
+        entry.omitted = 0;
+        return true;
+    }
+    entry
+        .children
+        .retain_mut(|child| keep_at_depth(child, depth - 1));
+    // Entries cut by --filelimit are only known to sit one level down
+    if depth > 1 {
+        entry.omitted = 0;
+    }
+    !entry.children.is_empty() || entry.omitted > 0
+}
+
+fn collect_at_depth(children: Vec<TreeEntry>, depth: usize, out: &mut Vec<TreeEntry>) {
+    for mut child in children {
+        if depth == 0 {
+            child.children.clear();
+            child.omitted = 0;
+            out.push(child);
+        } else {
+            collect_at_depth(std::mem::take(&mut child.children), depth - 1, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::{print_tree, print_trees, OutputFormat, PrintConfig};
+    use std::cell::RefCell;
+    use std::fs;
+
+    thread_local! {
+        /// Directories passed to `open_dir` on this thread
+        pub(super) static DIRS_OPENED: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn names(entry: &TreeEntry, prefix: &str, out: &mut Vec<String>) {
+        for child in &entry.children {
+            let name = format!("{}{}", prefix, child.name);
+            out.push(name.clone());
+            names(child, &format!("{}/", name), out);
+        }
+    }
+
+    fn walk_names(path: &Path, config: &TreeConfig) -> Vec<String> {
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(path, config, &mut stats, 0);
+        let mut out = Vec::new();
+        names(&tree, "", &mut out);
+        out
+    }
+
+    fn walk_json(path: &Path, config: &TreeConfig) -> (Vec<u8>, TreeStats) {
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(path, config, &mut stats, 0);
+        let print_config = PrintConfig {
+            output_format: OutputFormat::Json,
+            ..PrintConfig::default()
+        };
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &print_config, &stats).unwrap();
+        (out, stats)
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for a in 0..5 {
+            for b in 0..5 {
+                let sub = root.join(format!("dir{}/sub{}", a, b));
+                fs::create_dir_all(&sub).unwrap();
+                for f in 0..4 {
+                    fs::write(sub.join(format!("file{}.txt", f)), "x".repeat(f)).unwrap();
+                }
+            }
+        }
+
+        let sequential = TreeConfig::default();
+        let parallel = TreeConfig {
+            threads: Some(4),
+            ..TreeConfig::default()
+        };
+        let (seq_out, seq_stats) = walk_json(root, &sequential);
+        let (par_out, par_stats) = walk_json(root, &parallel);
+
+        assert_eq!(seq_out, par_out);
+        assert_eq!(seq_stats.directories, par_stats.directories);
+        assert_eq!(seq_stats.files, par_stats.files);
+        assert_eq!(par_stats.directories, 30);
+        assert_eq!(par_stats.files, 100);
+    }
+
+    #[test]
+    fn test_prune_with_pattern() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("docs/images")).unwrap();
+        fs::create_dir_all(root.join("src/util")).unwrap();
+        fs::create_dir(root.join("empty")).unwrap();
+        fs::write(root.join("docs/readme.md"), "").unwrap();
+        fs::write(root.join("docs/images/logo.png"), "").unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+        fs::write(root.join("src/util/notes.txt"), "").unwrap();
+
+        let mut filter = Filter::new();
+        filter.add_include("*.rs").unwrap();
+        let mut config = TreeConfig {
+            filter,
+            prune_empty: true,
+            ..TreeConfig::default()
+        };
+
+        // By default the pattern hides the directories themselves
+        assert!(walk_names(root, &config).is_empty());
+
+        config.filter.include_files_only = true;
+
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &config, &mut stats, 0);
+        let mut out = Vec::new();
+        names(&tree, "", &mut out);
+
+        assert_eq!(out, vec!["src", "src/main.rs"]);
+        assert_eq!(stats.directories, 1);
+        assert_eq!(stats.files, 1);
+    }
+
+    #[test]
+    fn test_only_dirs_with_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("hollow/a/b")).unwrap();
+        fs::create_dir(root.join("hollow/c")).unwrap();
+        fs::create_dir_all(root.join("full/x/y")).unwrap();
+        fs::write(root.join("full/x/y/file"), "").unwrap();
+        fs::create_dir(root.join("links")).unwrap();
+        std::os::unix::fs::symlink(root.join("full"), root.join("links/to-full")).unwrap();
+
+        let config = TreeConfig {
+            only_dirs_with_files: true,
+            ..TreeConfig::default()
+        };
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &config, &mut stats, 0);
+        let mut out = Vec::new();
+        names(&tree, "", &mut out);
+        assert_eq!(out, vec!["full", "full/x", "full/x/y", "full/x/y/file"]);
+        assert_eq!(stats.directories, 3);
+        assert_eq!(stats.files, 1);
+
+        // --prune keeps the directory holding only a symlinked directory
+        let config = TreeConfig {
+            prune_empty: true,
+            ..TreeConfig::default()
+        };
+        assert!(walk_names(root, &config).contains(&"links/to-full".to_string()));
+    }
+
+    #[test]
+    fn test_prune_keeps_empty_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = TreeConfig {
+            prune_empty: true,
+            ..TreeConfig::default()
+        };
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(dir.path(), &config, &mut stats, 0);
+        assert!(tree.is_dir);
+        assert!(tree.children.is_empty());
+        assert_eq!(stats.directories, 0);
+    }
+
+    #[test]
+    fn test_depth_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/a1")).unwrap();
+        fs::create_dir(root.join("b")).unwrap();
+        fs::write(root.join("f"), "").unwrap();
+        fs::write(root.join("a/x"), "").unwrap();
+        fs::write(root.join("a/a1/y"), "").unwrap();
+
+        // Four directories read (root, a, a1 and the empty b) holding six
+        // entries between them
+        let check = |stats: &TreeStats| {
+            assert_eq!(stats.max_depth_reached, 3);
+            assert_eq!((stats.listed_dirs, stats.listed_children), (4, 6));
+            assert_eq!(stats.average_branching(), 1.5);
+        };
+        for threads in [None, Some(2)] {
+            let config = TreeConfig {
+                threads,
+                ..TreeConfig::default()
+            };
+            let mut stats = TreeStats::default();
+            let tree = walk_directory(root, &config, &mut stats, 0);
+            check(&stats);
+            check(&TreeStats::from_tree(&tree));
+        }
+
+        let config = TreeConfig {
+            max_depth: Some(2),
+            ..TreeConfig::default()
+        };
+        let mut stats = TreeStats::default();
+        walk_directory(root, &config, &mut stats, 0);
+        assert_eq!(stats.max_depth_reached, 2);
+    }
+
+    #[test]
+    fn test_top_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        let sizes = [
+            ("small", 10),
+            ("a/big.log", 5000),
+            ("a/mid", 300),
+            ("a/b/huge", 9000),
+            ("a/b/tie1", 300),
+            ("tiny", 1),
+        ];
+        for (name, size) in sizes {
+            fs::write(root.join(name), vec![b'x'; size]).unwrap();
+        }
+
+        let top = |threads, pattern: Option<&str>| {
+            let mut config = TreeConfig {
+                top_files: 3,
+                threads,
+                ..TreeConfig::default()
+            };
+            if let Some(pattern) = pattern {
+                config.filter.add_include(pattern).unwrap();
+            }
+            let mut stats = TreeStats::default();
+            walk_directory(root, &config, &mut stats, 0);
+            let top: Vec<_> = stats
+                .largest_files
+                .sorted()
+                .into_iter()
+                .map(|(size, path)| (size, path.strip_prefix(root).unwrap().to_path_buf()))
+                .collect();
+            (top, stats)
+        };
+
+        // Of the two 300-byte files, the first by path wins
+        let expected = vec![
+            (9000, PathBuf::from("a/b/huge")),
+            (5000, PathBuf::from("a/big.log")),
+            (300, PathBuf::from("a/b/tie1")),
+        ];
+        assert_eq!(top(None, None).0, expected);
+        assert_eq!(top(Some(2), None).0, expected);
+
+        // Filtered-out files aren't candidates
+        let (filtered, stats) = top(None, Some("!*.log"));
+        assert_eq!(filtered[1], (300, PathBuf::from("a/b/tie1")));
+        assert_eq!(filtered[2], (300, PathBuf::from("a/mid")));
+
+        let print_config = PrintConfig {
+            colorize: false,
+            relative_to: Some(root.to_path_buf()),
+            ..PrintConfig::default()
+        };
+        let tree = TreeEntry::new(root.to_path_buf());
+        let mut out = Vec::new();
+        print_tree(&mut out, &tree, &print_config, &stats).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.ends_with("Largest files:\n  9000  a/b/huge\n   300  a/b/tie1\n   300  a/mid\n")
+        );
+
+        // Shown once for several roots, and even without the report
+        let print_config = PrintConfig {
+            no_report: true,
+            ..print_config
+        };
+        let mut other = TreeStats {
+            largest_files: LargestFiles::new(3),
+            ..TreeStats::default()
+        };
+        other.largest_files.offer(700, &root.join("other"));
+        let mut out = Vec::new();
+        print_trees(&mut out, &[(&tree, &stats), (&tree, &other)], &print_config).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("Largest files:").count(), 1);
+        assert!(
+            out.ends_with("Largest files:\n  9000  a/b/huge\n   700  other\n   300  a/b/tie1\n")
+        );
+    }
+
+    #[test]
+    fn test_entry_depths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        fs::write(root.join("a/b/c/deep"), "").unwrap();
+        fs::write(root.join("a/mid"), "").unwrap();
+        fs::write(root.join("top"), "").unwrap();
+
+        let expected = [
+            ("", 0),
+            ("a", 1),
+            ("a/b", 2),
+            ("a/b/c", 3),
+            ("a/b/c/deep", 4),
+            ("a/mid", 2),
+            ("top", 1),
+        ];
+        let check = |tree: &TreeEntry| {
+            for (depth, entry) in tree.iter() {
+                assert_eq!(entry.depth, depth, "{}", entry.path.display());
+            }
+            let depths: Vec<_> = tree
+                .iter()
+                .map(|(_, e)| {
+                    let relative = e.path.strip_prefix(root).unwrap();
+                    (relative.to_string_lossy().to_string(), e.depth)
+                })
+                .collect();
+            let expected: Vec<_> = expected.iter().map(|(p, d)| (p.to_string(), *d)).collect();
+            assert_eq!(depths, expected);
+        };
+
+        for threads in [None, Some(2)] {
+            let config = TreeConfig {
+                threads,
+                ..TreeConfig::default()
+            };
+            check(&walk_directory(root, &config, &mut TreeStats::default(), 0));
+        }
+
+        let mut streamed = Vec::new();
+        walk_streaming(root, &TreeConfig::default(), |entry, depth| {
+            streamed.push((entry.depth, depth))
+        });
+        assert_eq!(streamed.len(), expected.len());
+        assert!(streamed.iter().all(|(field, depth)| field == depth));
+
+        let paths: Vec<_> = ["", "a/b/c/deep", "a/mid", "top"]
+            .iter()
+            .map(|p| root.join(p))
+            .collect();
+        let (tree, _) = tree_from_paths(&paths, &TreeConfig::default(), &mut TreeStats::default());
+        check(&tree.unwrap());
+    }
+
+    #[test]
+    fn test_collapse_chains() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("com/example/foo/bar")).unwrap();
+        fs::write(root.join("com/example/foo/bar/Main.java"), "").unwrap();
+        fs::write(root.join("com/example/foo/bar/Util.java"), "").unwrap();
+        // Stops at a directory with a file, or with several children
+        fs::create_dir_all(root.join("docs/api/v1")).unwrap();
+        fs::write(root.join("docs/api/index.md"), "").unwrap();
+        fs::create_dir_all(root.join("lib/a")).unwrap();
+        fs::create_dir_all(root.join("lib/b")).unwrap();
+        fs::create_dir(root.join("only")).unwrap();
+        fs::write(root.join("only/file"), "").unwrap();
+
+        let mut stats = TreeStats::default();
+        let mut tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        collapse_chains(&mut tree);
+        let mut out = Vec::new();
+        names(&tree, "", &mut out);
+        assert_eq!(
+            out,
+            vec![
+                "com/example/foo/bar",
+                "com/example/foo/bar/Main.java",
+                "com/example/foo/bar/Util.java",
+                "docs/api",
+                "docs/api/index.md",
+                "docs/api/v1",
+                "lib",
+                "lib/a",
+                "lib/b",
+                "only",
+                "only/file",
+            ]
+        );
+        assert_eq!(tree.children[0].path, root.join("com/example/foo/bar"));
+
+        // Merged entries sit at the outermost directory's depth
+        let depths: Vec<_> = tree.iter().map(|(depth, e)| (depth, e.depth)).collect();
+        assert!(depths.iter().all(|(shown, stored)| shown == stored));
+        assert_eq!(tree.children[0].depth, 1);
+        assert_eq!(tree.children[0].children[0].depth, 2);
+    }
+
+    #[test]
+    fn test_select_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        fs::create_dir_all(root.join("d/e")).unwrap();
+        fs::create_dir(root.join("empty")).unwrap();
+        fs::write(root.join("a/b/c/deep"), "").unwrap();
+        fs::write(root.join("a/f"), "").unwrap();
+        fs::write(root.join("top"), "").unwrap();
+
+        let selected = |flatten| {
+            let mut stats = TreeStats::default();
+            let mut tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+            select_depth(&mut tree, 2, flatten);
+            let depths: Vec<_> = tree.iter().map(|(depth, _)| depth).collect();
+            let mut out = Vec::new();
+            names(&tree, "", &mut out);
+            (out, depths)
+        };
+
+        let (names, depths) = selected(false);
+        assert_eq!(names, vec!["a", "a/b", "a/f", "d", "d/e"]);
+        assert_eq!(depths, vec![0, 1, 2, 2, 1, 2]);
+
+        let (names, depths) = selected(true);
+        assert_eq!(names, vec!["b", "f", "e"]);
+        assert_eq!(depths, vec![0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_level_limits_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        fs::write(root.join("a/b/c/deep"), "").unwrap();
+        fs::write(root.join("top"), "").unwrap();
+
+        let level = |max_depth| TreeConfig {
+            max_depth: Some(max_depth),
+            ..TreeConfig::default()
+        };
+        let deepest = |max_depth| {
+            let mut stats = TreeStats::default();
+            let tree = walk_directory(root, &level(max_depth), &mut stats, 0);
+            tree.iter().map(|(depth, _)| depth).max().unwrap()
+        };
+
+        assert_eq!(deepest(1), 1);
+        assert_eq!(deepest(2), 2);
+        assert_eq!(deepest(3), 3);
+        assert_eq!(walk_names(root, &level(1)), vec!["a", "top"]);
+        assert_eq!(walk_names(root, &level(2)), vec!["a", "a/b", "top"]);
+
+        // Only directories cut off by the limit are marked unread
+        let tree = walk_directory(root, &level(2), &mut TreeStats::default(), 0);
+        let unread: Vec<&str> = tree
+            .iter()
+            .filter(|(_, e)| e.unread)
+            .map(|(_, e)| e.name.as_str())
+            .collect();
+        assert_eq!(unread, vec!["b"]);
+    }
+
+    #[test]
+    fn test_total_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("one.txt"), "x".repeat(100)).unwrap();
+        fs::write(root.join("a/two.txt"), "x".repeat(250)).unwrap();
+        fs::write(root.join("a/b/three.rs"), "x".repeat(4000)).unwrap();
+
+        let mut stats = TreeStats::default();
+        walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        assert_eq!(stats.total_size, 4350);
+
+        let mut filter = Filter::new();
+        filter.include_files_only = true;
+        filter.add_include("*.txt").unwrap();
+        let config = TreeConfig {
+            filter,
+            ..TreeConfig::default()
+        };
+        let mut stats = TreeStats::default();
+        walk_directory(root, &config, &mut stats, 0);
+        assert_eq!(stats.total_size, 350);
+    }
+
+    #[test]
+    fn test_dir_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir(root.join("c")).unwrap();
+        fs::write(root.join("one.txt"), "x".repeat(100)).unwrap();
+        fs::write(root.join("a/two.txt"), "x".repeat(250)).unwrap();
+        fs::write(root.join("a/b/three.rs"), "x".repeat(4000)).unwrap();
+
+        let mut stats = TreeStats::default();
+        let mut tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        compute_dir_sizes(&mut tree);
+
+        let a = &tree.children[0];
+        let b = &a.children[0];
+        let c = &tree.children[1];
+        assert_eq!(b.aggregate_size, Some(4000));
+        assert_eq!(a.aggregate_size, Some(4250));
+        assert_eq!(c.aggregate_size, Some(0));
+        assert_eq!(tree.aggregate_size, Some(4350));
+        assert_eq!(
+            tree.display_size(),
+            tree.children
+                .iter()
+                .map(TreeEntry::display_size)
+                .sum::<u64>()
+        );
+    }
+
+    #[test]
+    fn test_iter_pre_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir(root.join("c")).unwrap();
+        fs::write(root.join("a/b/x"), "").unwrap();
+        fs::write(root.join("a/y"), "").unwrap();
+        fs::write(root.join("c/z"), "").unwrap();
+        fs::write(root.join("d"), "").unwrap();
+
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        let visited: Vec<(usize, &str)> = tree
+            .iter()
+            .skip(1)
+            .map(|(depth, entry)| (depth, entry.name.as_str()))
+            .collect();
+
+        assert_eq!(
+            visited,
+            vec![
+                (1, "a"),
+                (2, "b"),
+                (3, "x"),
+                (2, "y"),
+                (1, "c"),
+                (2, "z"),
+                (1, "d"),
+            ]
+        );
+        assert_eq!(tree.iter().next().map(|(depth, _)| depth), Some(0));
+        assert_eq!(tree.iter().count(), 8);
+    }
+
+    #[test]
+    fn test_dereference_args() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::create_dir_all(target.join("inner")).unwrap();
+        fs::write(target.join("file"), "").unwrap();
+        std::os::unix::fs::symlink(&target, dir.path().join("link")).unwrap();
+        std::os::unix::fs::symlink(&target, target.join("inner/back")).unwrap();
+
+        let link = dir.path().join("link");
+        assert!(walk_names(&link, &TreeConfig::default()).is_empty());
+
+        let config = TreeConfig {
+            dereference_args: true,
+            ..TreeConfig::default()
+        };
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(&link, &config, &mut stats, 0);
+        assert!(tree.is_symlink);
+        let mut out = Vec::new();
+        names(&tree, "", &mut out);
+        assert_eq!(out, vec!["file", "inner", "inner/back"]);
+    }
+
+    #[test]
+    fn test_full_path_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/util")).unwrap();
+        fs::create_dir(root.join("tests")).unwrap();
+        for file in ["build.rs", "src/main.rs", "src/util/mod.rs", "tests/cli.rs"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        let mut filter = Filter::new();
+        filter.include_files_only = true;
+        filter.add_include("src/**/*.rs").unwrap();
+        let mut config = TreeConfig {
+            filter,
+            prune_empty: true,
+            ..TreeConfig::default()
+        };
+        assert!(walk_names(root, &config).is_empty());
+
+        config.filter.match_full_path = true;
+        assert_eq!(
+            walk_names(root, &config),
+            vec!["src", "src/main.rs", "src/util", "src/util/mod.rs"]
+        );
+
+        let mut filter = Filter::new();
+        filter.include_files_only = true;
+        filter.add_include("*.rs").unwrap();
+        config.filter = filter;
+        assert_eq!(walk_names(root, &config).len(), 7);
+    }
+
+    #[test]
+    fn test_find_matching() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::create_dir(root.join("empty")).unwrap();
+        for file in ["a.rs", "b.txt", "src/c.rs", "src/nested/d.rs", "src/e.md"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        let mut filter = Filter::new();
+        filter.include_files_only = true;
+        filter.add_include("*.rs").unwrap();
+        filter.add_exclude("d.rs").unwrap();
+        let config = TreeConfig {
+            filter,
+            ..TreeConfig::default()
+        };
+        let found = find_matching(root, &config);
+        assert_eq!(found, vec![root.join("a.rs"), root.join("src/c.rs")]);
+
+        // Same set as the files left in the filtered tree
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &config, &mut stats, 0);
+        let mut leaves: Vec<PathBuf> = tree
+            .iter()
+            .filter(|(_, entry)| !entry.is_dir)
+            .map(|(_, entry)| entry.path.clone())
+            .collect();
+        leaves.sort();
+        assert_eq!(found, leaves);
+    }
+
+    #[test]
+    fn test_walk_streaming() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir(root.join("c")).unwrap();
+        fs::write(root.join("a/b/one.txt"), "").unwrap();
+        fs::write(root.join("a/two.txt"), "").unwrap();
+        fs::write(root.join("three.txt"), "").unwrap();
+
+        let config = TreeConfig::default();
+        let mut streamed = Vec::new();
+        walk_streaming(root, &config, |entry, depth| {
+            assert!(entry.children.is_empty());
+            streamed.push((entry.name.clone(), depth));
+        });
+
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &config, &mut stats, 0);
+        assert_eq!(streamed.len(), tree.iter().count());
+        assert_eq!(streamed.len(), 1 + stats.directories + stats.files);
+        let expected: Vec<_> = tree.iter().map(|(d, e)| (e.name.clone(), d)).collect();
+        assert_eq!(streamed, expected);
+    }
+
+    #[test]
+    fn test_excluded_dirs_never_opened() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("node_modules/pkg/lib")).unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("node_modules/pkg/lib/index.js"), "").unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+
+        let mut filter = Filter::new();
+        filter.add_exclude("node_modules").unwrap();
+        let config = TreeConfig {
+            filter,
+            ..TreeConfig::default()
+        };
+        let opened_by = |walk: &dyn Fn()| {
+            DIRS_OPENED.with(|opened| opened.borrow_mut().clear());
+            walk();
+            DIRS_OPENED.with(|opened| opened.take())
+        };
+        let expected = vec![root.to_path_buf(), root.join("src")];
+
+        let opened = opened_by(&|| {
+            walk_directory(root, &config, &mut TreeStats::default(), 0);
+        });
+        assert_eq!(opened, expected);
+        let opened = opened_by(&|| {
+            find_matching(root, &config);
+        });
+        assert_eq!(opened, expected);
+        let opened = opened_by(&|| walk_streaming(root, &config, |_, _| {}));
+        assert_eq!(opened, expected);
+    }
+
+    #[test]
+    fn test_sort_case_independent_of_filter_case() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for name in ["c.TXT", "B.txt", "a.txt", "C.md"] {
+            fs::write(root.join(name), "").unwrap();
+        }
+
+        let names = |ignore_case: bool, case_sensitive_sort: bool| {
+            let mut filter = Filter::new();
+            filter.ignore_case = ignore_case;
+            filter.add_include("*.txt").unwrap();
+            let config = TreeConfig {
+                filter,
+                case_sensitive_sort,
+                ..TreeConfig::default()
+            };
+            walk_names(root, &config)
+        };
+        assert_eq!(names(false, false), vec!["a.txt", "B.txt"]);
+        assert_eq!(names(false, true), vec!["B.txt", "a.txt"]);
+        assert_eq!(names(true, false), vec!["a.txt", "B.txt", "c.TXT"]);
+        assert_eq!(names(true, true), vec!["B.txt", "a.txt", "c.TXT"]);
+    }
+
+    #[test]
+    fn test_match_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/util")).unwrap();
+        fs::create_dir(root.join("docs")).unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+        fs::write(root.join("src/util/notes.txt"), "").unwrap();
+        fs::write(root.join("src/util/skip.log"), "").unwrap();
+        fs::write(root.join("docs/src.md"), "").unwrap();
+        fs::write(root.join("top.rs"), "").unwrap();
+
+        let mut filter = Filter::new();
+        filter.include_files_only = true;
+        filter.add_include("src").unwrap();
+        filter.add_exclude("*.log").unwrap();
+        let mut config = TreeConfig {
+            filter,
+            ..TreeConfig::default()
+        };
+
+        // Without the flag directories are only traversed, never matched
+        assert_eq!(walk_names(root, &config), vec!["docs", "src", "src/util"]);
+
+        // A matching directory lists its whole subtree, minus exclusions
+        config.filter.match_dirs = true;
+        let expected = [
+            "docs",
+            "src",
+            "src/main.rs",
+            "src/util",
+            "src/util/notes.txt",
+        ];
+        assert_eq!(walk_names(root, &config), expected);
+        assert_eq!(
+            find_matching(root, &config),
+            vec![root.join("src/main.rs"), root.join("src/util/notes.txt")]
+        );
+    }
+
+    #[test]
+    fn test_traversal_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/deep")).unwrap();
+        fs::create_dir(root.join("b")).unwrap();
+        fs::write(root.join("a/deep/file"), "").unwrap();
+        fs::write(root.join("a/x"), "").unwrap();
+        fs::write(root.join("b/y"), "").unwrap();
+        fs::write(root.join("c"), "").unwrap();
+
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        let visit = |order| {
+            tree.iter_in(order)
+                .skip(1)
+                .map(|(depth, entry)| format!("{}:{}", depth, entry.name))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            visit(TraversalOrder::DepthFirst),
+            vec!["1:a", "2:deep", "3:file", "2:x", "1:b", "2:y", "1:c"]
+        );
+        assert_eq!(
+            visit(TraversalOrder::BreadthFirst),
+            vec!["1:a", "1:b", "1:c", "2:deep", "2:x", "2:y", "3:file"]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_total_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("big/nested")).unwrap();
+        fs::create_dir(root.join("small")).unwrap();
+        fs::write(root.join("big/nested/blob"), vec![0u8; 64 * 1024]).unwrap();
+        fs::write(root.join("big/more"), vec![0u8; 32 * 1024]).unwrap();
+        fs::write(root.join("small/note"), "hi").unwrap();
+        fs::write(root.join("medium"), vec![0u8; 8 * 1024]).unwrap();
+        // Four links to one 16 KiB file count once
+        fs::create_dir(root.join("links")).unwrap();
+        fs::write(root.join("links/a"), vec![0u8; 16 * 1024]).unwrap();
+        for name in ["b", "c", "d"] {
+            fs::hard_link(root.join("links/a"), root.join("links").join(name)).unwrap();
+        }
+
+        let config = TreeConfig {
+            sort_key: SortKey::TotalSize,
+            ..TreeConfig::default()
+        };
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &config, &mut stats, 0);
+
+        let order: Vec<_> = tree.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(order, vec!["big", "links", "medium", "small"]);
+        assert_eq!(tree.children[0].total_size(), 96 * 1024);
+        assert_eq!(tree.children[1].total_size(), 16 * 1024);
+        // Sizes are only aggregated for --du
+        assert!(tree.iter().all(|(_, e)| e.aggregate_size.is_none()));
+    }
+
+    #[test]
+    fn test_ignore_vcs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for name in [".git/objects", ".hg", "CVS", ".config", "src"] {
+            fs::create_dir_all(root.join(name)).unwrap();
+        }
+        // Only directories are matched
+        fs::write(root.join("src/.svn"), "").unwrap();
+
+        let config = TreeConfig {
+            show_hidden: true,
+            ignore_vcs: true,
+            ..TreeConfig::default()
+        };
+        assert_eq!(
+            walk_names(root, &config),
+            vec![".config", "src", "src/.svn"]
+        );
+    }
+
+    #[test]
+    fn test_max_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..50 {
+            fs::write(dir.path().join(format!("file{:02}", i)), "").unwrap();
+        }
+
+        let config = TreeConfig {
+            max_entries: Some(5),
+            ..TreeConfig::default()
+        };
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(dir.path(), &config, &mut stats, 0);
+
+        let listed: Vec<_> = tree.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            listed,
+            vec!["file00", "file01", "file02", "file03", "file04"]
+        );
+        assert_eq!(tree.omitted, 45);
+        assert_eq!(stats.files, 50);
+    }
+
+    #[test]
+    fn test_special_file_types() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let fifo = root.join("pipe");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let socket = root.join("sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        std::os::unix::fs::symlink("pipe", root.join("pipe_link")).unwrap();
+
+        let pipe = TreeEntry::new(fifo);
+        assert!(pipe.is_fifo() && !pipe.is_socket());
+        assert_eq!(pipe.type_indicator(), "|");
+        assert!(pipe.permissions_string().starts_with('p'));
+
+        let sock = TreeEntry::new(socket);
+        assert!(sock.is_socket() && !sock.is_fifo());
+        assert_eq!(sock.type_indicator(), "=");
+        assert!(sock.permissions_string().starts_with('s'));
+
+        // A link to a pipe is still marked as a link
+        assert_eq!(TreeEntry::new(root.join("pipe_link")).type_indicator(), "@");
+
+        let null = TreeEntry::new("/dev/null".into());
+        assert!(null.is_char_device() && !null.is_block_device());
+        assert_eq!(null.type_indicator(), "");
+        assert!(null.permissions_string().starts_with('c'));
+    }
+
+    #[test]
+    fn test_entry_from_listing_matches_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("file"), "hello").unwrap();
+        std::os::unix::fs::symlink("dir", root.join("dir_link")).unwrap();
+        std::os::unix::fs::symlink("file", root.join("file_link")).unwrap();
+        std::os::unix::fs::symlink("missing", root.join("broken")).unwrap();
+
+        for dir_entry in fs::read_dir(root).unwrap().flatten() {
+            let listed = TreeEntry::from_dir_entry(&dir_entry);
+            let looked_up = TreeEntry::new(dir_entry.path());
+            assert_eq!(listed.name, looked_up.name);
+            assert_eq!(
+                (listed.is_dir, listed.is_symlink, listed.size()),
+                (looked_up.is_dir, looked_up.is_symlink, looked_up.size()),
+                "{}",
+                listed.name
+            );
+            assert_eq!(listed.symlink_target, looked_up.symlink_target);
+            assert_eq!(listed.is_broken_symlink(), looked_up.is_broken_symlink());
+        }
+    }
+
+    #[test]
+    fn test_progress() {
+        use std::sync::Mutex;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        for i in 0..12 {
+            fs::write(root.join(format!("file{}", i)), "").unwrap();
+            fs::write(root.join(format!("sub/file{}", i)), "").unwrap();
+        }
+        fs::write(root.join(".hidden"), "").unwrap();
+
+        // 26 entries: sub, .hidden and 24 files; hidden ones count as scanned
+        for threads in [None, Some(4)] {
+            let reports = Arc::new(Mutex::new(Vec::new()));
+            let sink = Arc::clone(&reports);
+            let config = TreeConfigBuilder::new()
+                .threads(threads)
+                .progress(10, move |scanned| sink.lock().unwrap().push(scanned))
+                .build();
+            walk_directory(root, &config, &mut TreeStats::default(), 0);
+
+            let mut reports = reports.lock().unwrap().clone();
+            reports.sort();
+            assert_eq!(reports, [10, 20]);
+            assert_eq!(config.progress.unwrap().scanned(), 26);
+        }
+    }
+
+    #[test]
+    fn test_custom_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/big.rs"), "x".repeat(100)).unwrap();
+        fs::write(root.join("src/small.rs"), "x").unwrap();
+        fs::write(root.join("huge.bin"), "x".repeat(1000)).unwrap();
+
+        let config = TreeConfigBuilder::new()
+            .custom_filter(|entry| entry.is_dir || entry.size() < 50)
+            .build();
+        assert_eq!(walk_names(root, &config), vec!["src", "src/small.rs"]);
+        assert_eq!(
+            find_matching(root, &config),
+            vec![root.join("src/small.rs")]
+        );
+
+        // Rejecting a directory skips everything beneath it
+        let config = TreeConfigBuilder::new()
+            .custom_filter(|entry| entry.name != "src")
+            .threads(Some(2))
+            .build();
+        assert_eq!(walk_names(root, &config), vec!["huge.bin"]);
+        assert_eq!(
+            format!("{:?}", config.custom_filter),
+            "Some(EntryPredicate(..))"
+        );
+    }
+
+    #[test]
+    fn test_treeignore() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+        fs::write(root.join("src/main.rs.orig"), "").unwrap();
+        fs::write(root.join("notes.log"), "").unwrap();
+        fs::write(
+            root.join(TREEIGNORE_FILE),
+            "# build output\ntarget\n\n*.{log,orig}\n",
+        )
+        .unwrap();
+
+        let config = TreeConfig::default();
+        assert_eq!(walk_names(root, &config), vec!["src", "src/main.rs"]);
+        assert_eq!(find_matching(root, &config), vec![root.join("src/main.rs")]);
+
+        let config = TreeConfig {
+            respect_treeignore: false,
+            ..TreeConfig::default()
+        };
+        assert_eq!(walk_names(root, &config).len(), 6);
+
+        // A bad pattern is reported on the root and the file is skipped
+        fs::write(root.join(TREEIGNORE_FILE), "target\n[\n").unwrap();
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        assert!(tree
+            .error
+            .as_deref()
+            .unwrap()
+            .starts_with("invalid pattern at .treeignore:2: "));
+        assert_eq!(stats.errors, 1);
+        assert!(tree.children.iter().any(|c| c.name == "target"));
+    }
+
+    #[test]
+    fn test_file_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("big")).unwrap();
+        fs::create_dir_all(root.join("small")).unwrap();
+        for i in 0..5 {
+            fs::write(root.join(format!("big/file{}", i)), "").unwrap();
+        }
+        fs::write(root.join("small/only"), "").unwrap();
+
+        let config = TreeConfig {
+            file_limit: Some(3),
+            ..TreeConfig::default()
+        };
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &config, &mut stats, 0);
+
+        let big = &tree.children[0];
+        assert!(big.children.is_empty());
+        assert_eq!(big.error, None);
+        assert_eq!(
+            big.note.as_deref(),
+            Some("5 entries exceeds filelimit, not opening dir")
+        );
+        assert_eq!(
+            walk_names(root, &config),
+            vec!["big", "small", "small/only"]
+        );
+        assert_eq!((stats.directories, stats.files), (2, 1));
+        // An unopened directory isn't an unreadable one
+        assert_eq!(stats.errors, 0);
+        assert_eq!(TreeStats::from_tree(&tree).errors, 0);
+
+        // ...and it still ends a chain, keeping its note
+        fs::create_dir(root.join("small/big")).unwrap();
+        fs::rename(root.join("big"), root.join("small/big/big")).unwrap();
+        fs::remove_file(root.join("small/only")).unwrap();
+        let mut tree = walk_directory(root, &config, &mut TreeStats::default(), 0);
+        collapse_chains(&mut tree);
+        let merged = &tree.children[0];
+        assert_eq!(merged.name, "small/big/big");
+        assert!(merged.note.is_some() && merged.error.is_none());
+    }
+
+    #[test]
+    fn test_tree_from_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir(root.join("c")).unwrap();
+        fs::write(root.join("a/b/file"), "").unwrap();
+        fs::write(root.join("c/other"), "").unwrap();
+
+        let paths: Vec<PathBuf> = vec![
+            root.to_path_buf(),
+            root.join("c"),
+            root.join("a/b/file"),
+            root.join("missing"),
+            PathBuf::from("/elsewhere"),
+        ];
+        let mut stats = TreeStats::default();
+        let (tree, errors) = tree_from_paths(&paths, &TreeConfig::default(), &mut stats);
+        let tree = tree.unwrap();
+
+        let mut out = Vec::new();
+        names(&tree, "", &mut out);
+        assert_eq!(out, vec!["a", "a/b", "a/b/file", "c"]);
+        assert_eq!(stats.directories, 3);
+        assert_eq!(stats.files, 1);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("missing"));
+        assert!(errors[1].contains("/elsewhere"));
+
+        // Filtered paths are dropped along with everything below them
+        fs::write(root.join("c/.hidden"), "").unwrap();
+        fs::write(root.join("c/skip.log"), "").unwrap();
+        let paths: Vec<PathBuf> = ["", "a/b/file", "c/.hidden", "c/skip.log", "c/other"]
+            .iter()
+            .map(|p| root.join(p))
+            .collect();
+        let mut filter = Filter::new();
+        filter.add_exclude("b").unwrap();
+        filter.add_exclude("*.log").unwrap();
+        let config = TreeConfig {
+            filter,
+            ..TreeConfig::default()
+        };
+        let (tree, _) = tree_from_paths(&paths, &config, &mut TreeStats::default());
+        let mut out = Vec::new();
+        names(&tree.unwrap(), "", &mut out);
+        assert_eq!(out, vec!["a", "c", "c/other"]);
+    }
+
+    #[test]
+    fn test_extension_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.rs", "b.RS", "c.rs", "Cargo.toml", "x.toml", "Makefile"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        fs::create_dir(dir.path().join("sub.d")).unwrap();
+
+        let mut stats = TreeStats::default();
+        walk_directory(dir.path(), &TreeConfig::default(), &mut stats, 0);
+
+        assert_eq!(stats.extensions.len(), 3);
+        assert_eq!(stats.extensions["rs"], 3);
+        assert_eq!(stats.extensions["toml"], 2);
+        assert_eq!(stats.extensions[""], 1);
+    }
+
+    #[test]
+    fn test_size_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("small"), "x".repeat(10)).unwrap();
+        fs::write(root.join("medium"), "x".repeat(2000)).unwrap();
+        fs::write(root.join("sub/large"), "x".repeat(50_000)).unwrap();
+        // Has no size or mode of its own to test, so no filter lets it through
+        std::os::unix::fs::symlink("missing", root.join("broken")).unwrap();
+
+        let mut config = TreeConfig::default();
+        config.filter.min_size = Some(1024);
+        assert_eq!(
+            walk_names(root, &config),
+            vec!["medium", "sub", "sub/large"]
+        );
+
+        config.filter.max_size = Some(10 * 1024);
+        assert_eq!(walk_names(root, &config), vec!["medium", "sub"]);
+
+        fs::set_permissions(root.join("small"), fs::Permissions::from_mode(0o644)).unwrap();
+        fs::set_permissions(root.join("medium"), fs::Permissions::from_mode(0o600)).unwrap();
+        fs::set_permissions(root.join("sub/large"), fs::Permissions::from_mode(0o640)).unwrap();
+        let mut config = TreeConfig::default();
+        config.filter.perm = Some("0644".parse().unwrap());
+        assert_eq!(walk_names(root, &config), vec!["small", "sub"]);
+    }
+
+    #[test]
+    fn test_time_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let now = SystemTime::now();
+        let day = std::time::Duration::from_secs(24 * 60 * 60);
+        fs::create_dir(root.join("sub")).unwrap();
+        for (name, age) in [("old", 30), ("week", 5), ("sub/new", 0)] {
+            let file = fs::File::create(root.join(name)).unwrap();
+            file.set_modified(now - day * age).unwrap();
+        }
+
+        let mut config = TreeConfig::default();
+        config.filter.newer_than = Some(now - day * 7);
+        assert_eq!(walk_names(root, &config), vec!["sub", "sub/new", "week"]);
+
+        config.filter.older_than = Some(now - day);
+        assert_eq!(walk_names(root, &config), vec!["sub", "week"]);
+    }
+
+    #[test]
+    fn test_block_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        // Just over one 4 KiB block, so the allocation rounds up
+        fs::write(root.join("sub/data"), "x".repeat(4097)).unwrap();
+        fs::write(root.join("tiny"), "x").unwrap();
+
+        let mut stats = TreeStats::default();
+        let mut tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        let data = &tree.children[0].children[0];
+        assert_eq!(data.size(), 4097);
+        let st_blocks = fs::metadata(root.join("sub/data")).unwrap().blocks();
+        assert_eq!(data.blocks_size(), st_blocks * 512);
+
+        let blocks: u64 = tree
+            .iter()
+            .filter(|(_, e)| !e.is_dir)
+            .map(|(_, e)| e.blocks_size())
+            .sum();
+        assert_eq!(compute_dir_block_sizes(&mut tree), blocks);
+        assert_eq!(compute_dir_sizes(&mut tree), 4098);
+    }
+
+    #[test]
+    fn test_count_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {\n    run();\n}\n").unwrap();
+        fs::write(root.join("src/lib.rs"), "pub fn run() {}\n").unwrap();
+        // No trailing newline, so only one line counts, as with wc -l
+        fs::write(root.join("notes.txt"), "one\ntwo").unwrap();
+        fs::write(root.join("image.bin"), b"\x89PNG\n\0\n\n").unwrap();
+
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        stats.count_lines(&tree);
+        assert_eq!(stats.total_lines, 5);
+
+        // Only the files left after filtering are read
+        let mut filter = Filter::new();
+        filter.include_files_only = true;
+        filter.add_include("*.rs").unwrap();
+        let config = TreeConfig {
+            filter,
+            ..TreeConfig::default()
+        };
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &config, &mut stats, 0);
+        stats.count_lines(&tree);
+        assert_eq!(stats.total_lines, 4);
+    }
+
+    #[test]
+    fn test_dir_sizes_count_hard_links_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("data"), "x".repeat(1000)).unwrap();
+        fs::hard_link(root.join("data"), root.join("sub/link")).unwrap();
+        fs::write(root.join("sub/other"), "x".repeat(10)).unwrap();
+
+        let mut stats = TreeStats::default();
+        let mut tree = walk_directory(root, &TreeConfig::default(), &mut stats, 0);
+        compute_dir_sizes(&mut tree);
+
+        assert_eq!(tree.aggregate_size, Some(1010));
+        assert_eq!(tree.children[1].name, "sub");
+        assert_eq!(tree.children[1].aggregate_size, Some(10));
+    }
+
+    #[test]
+    fn test_crosses_filesystem() {
+        assert!(!crosses_filesystem(None, Some(5)));
+        assert!(!crosses_filesystem(Some(5), Some(5)));
+        assert!(crosses_filesystem(Some(5), Some(6)));
+        assert!(!crosses_filesystem(Some(5), None));
+    }
+
+    #[test]
+    fn test_xdev_same_filesystem_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/file"), "").unwrap();
+
+        let config = TreeConfig {
+            stay_on_filesystem: true,
+            ..TreeConfig::default()
+        };
+        assert_eq!(walk_names(root, &config), vec!["a", "a/b", "a/b/file"]);
+    }
+
+    #[test]
+    fn test_symlink_loop_terminates() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        std::os::unix::fs::symlink("..", root.join("sub/up")).unwrap();
+
+        let config = TreeConfig {
+            follow_symlinks: true,
+            ..TreeConfig::default()
+        };
+        let mut stats = TreeStats::default();
+        let tree = walk_directory(root, &config, &mut stats, 0);
+
+        let up = &tree.children[0].children[0];
+        assert_eq!(up.name, "up");
+        assert!(up.is_symlink);
+        assert!(up.children.is_empty());
+        assert_eq!(up.note.as_deref(), Some("recursive, not followed"));
+        assert!(up.error.is_none());
+        assert_eq!(stats.errors, 0);
+    }
+
+    #[test]
+    fn test_symlink_not_followed_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("real")).unwrap();
+        fs::write(root.join("real/file"), "").unwrap();
+        std::os::unix::fs::symlink("real", root.join("link")).unwrap();
+
+        let mut config = TreeConfig::default();
+        assert_eq!(walk_names(root, &config), vec!["link", "real", "real/file"]);
+
+        config.follow_symlinks = true;
+        assert_eq!(
+            walk_names(root, &config),
+            vec!["link", "link/file", "real", "real/file"]
+        );
+    }
+
+    #[test]
+    fn test_nested_gitignore() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::create_dir_all(root.join("src/gen")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.log\n!keep.log\n").unwrap();
+        fs::write(root.join("src/.gitignore"), "gen\n").unwrap();
+        fs::write(root.join("target/debug/app"), "").unwrap();
+        fs::write(root.join("build.log"), "").unwrap();
+        fs::write(root.join("keep.log"), "").unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+        fs::write(root.join("src/gen/out.rs"), "").unwrap();
+        fs::create_dir(root.join("gen")).unwrap();
+
+        let config = TreeConfig {
+            respect_gitignore: true,
+            ..TreeConfig::default()
+        };
+        assert_eq!(
+            walk_names(root, &config),
+            vec!["gen", "keep.log", "src", "src/main.rs"]
+        );
+    }
 }